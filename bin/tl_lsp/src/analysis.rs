@@ -0,0 +1,379 @@
+use gen::formatter::Rustifiable;
+use parser::{
+    combinators::{schema_with_diagnostics, ParseOptions},
+    errors::Diagnostic,
+    index::{Decl, SchemaIndex},
+    span::Span,
+    types::{Arg, CombinatorDecl, IdentNs, ResType, TLSchema, Term},
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// LSP `SymbolKind`s of the declarations in a document.
+const SYMBOL_CLASS: i64 = 5;
+const SYMBOL_FIELD: i64 = 8;
+const SYMBOL_CONSTRUCTOR: i64 = 9;
+const SYMBOL_FUNCTION: i64 = 12;
+
+/// The LSP `DiagnosticSeverity` of errors.
+const SEVERITY_ERROR: i64 = 1;
+
+/// An open `.tl` file, parsed as far as it goes. Everything requests ask
+/// about is worked out here, once per version.
+pub struct Document {
+    /// The version the client gave the text.
+    pub version: Option<i64>,
+    text: String,
+    line_starts: Vec<usize>,
+    pub schema: TLSchema<'static>,
+    pub diagnostics: Vec<Diagnostic>,
+    occurrences: Vec<Occurrence>,
+    /// Hover text of the names in `occurrences` that have one.
+    hovers: HashMap<String, String>,
+    /// Where each name in `occurrences` is declared.
+    definitions: HashMap<String, Vec<Span>>,
+}
+
+/// A name written in the document.
+#[derive(Debug, Clone)]
+pub struct Occurrence {
+    pub name: String,
+    pub span: Span,
+    /// Whether this is the name of a declaration rather than a use of it.
+    pub decl: bool,
+}
+
+impl Document {
+    pub fn new(text: String, version: Option<i64>) -> Self {
+        let options = ParseOptions {
+            file_name: None,
+            recover: true,
+        };
+        let (schema, diagnostics) = schema_with_diagnostics(&text, options);
+        let schema = schema.into_owned();
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(p, _)| p + 1))
+            .collect();
+
+        let occurrences = occurrences(&schema);
+        let index = schema.index();
+        let mut hovers = HashMap::new();
+        let mut definitions = HashMap::new();
+        for o in &occurrences {
+            if definitions.contains_key(&o.name) {
+                continue;
+            }
+            if let Some(hover) = hover(&index, &o.name) {
+                hovers.insert(o.name.clone(), hover);
+            }
+            definitions.insert(o.name.clone(), definitions_of(&schema, &index, &o.name));
+        }
+
+        Self {
+            version,
+            text,
+            line_starts,
+            schema,
+            diagnostics,
+            occurrences,
+            hovers,
+            definitions,
+        }
+    }
+
+    /// Converts an LSP position, whose character is counted in UTF-16 code
+    /// units, to a byte offset.
+    pub fn offset(&self, position: &Value) -> Option<usize> {
+        let line = position.get("line")?.as_i64()? as usize;
+        let character = position.get("character")?.as_i64()? as usize;
+        let start = *self.line_starts.get(line)?;
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.text.len(), |e| e - 1);
+
+        let mut units = 0;
+        for (i, c) in self.text[start..end].char_indices() {
+            if units >= character {
+                return Some(start + i);
+            }
+            units += c.len_utf16();
+        }
+        Some(end)
+    }
+
+    pub fn position(&self, offset: usize) -> Value {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let start = self.line_starts[line];
+        let character = self.text[start..offset].encode_utf16().count();
+
+        json!({ "line": line, "character": character })
+    }
+
+    pub fn range(&self, span: Span) -> Value {
+        json!({ "start": self.position(span.start), "end": self.position(span.end) })
+    }
+
+    /// Parse errors, then validation errors once the whole file parses.
+    pub fn lsp_diagnostics(&self) -> Value {
+        let diagnostic = |span: Span, message: String| {
+            json!({
+                "range": self.range(span),
+                "severity": SEVERITY_ERROR,
+                "source": "tl",
+                "message": message,
+            })
+        };
+
+        let mut diagnostics = self
+            .diagnostics
+            .iter()
+            .map(|d| {
+                let span = Span {
+                    start: d.offset,
+                    end: d.offset,
+                    ..Default::default()
+                };
+                diagnostic(span, d.message.clone())
+            })
+            .collect::<Vec<_>>();
+        if diagnostics.is_empty() {
+            diagnostics.extend(
+                self.schema
+                    .validate()
+                    .iter()
+                    .map(|e| diagnostic(e.span(), e.message())),
+            );
+        }
+        Value::Array(diagnostics)
+    }
+
+    /// Every declaration name and type reference, in source order.
+    pub fn occurrences(&self) -> &[Occurrence] {
+        &self.occurrences
+    }
+
+    pub fn occurrence_at(&self, offset: usize) -> Option<&Occurrence> {
+        self.occurrences
+            .iter()
+            .find(|o| o.span.start <= offset && offset <= o.span.end)
+    }
+
+    /// Markdown describing the declaration or type called `name`.
+    pub fn hover(&self, name: &str) -> Option<&str> {
+        self.hovers.get(name).map(String::as_str)
+    }
+
+    /// Where `name` is declared: the declaration itself, or the constructors
+    /// of the type.
+    pub fn definitions(&self, name: &str) -> &[Span] {
+        self.definitions.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn symbols(&self) -> Value {
+        let symbol = |name: String, detail: String, kind: i64, span, selection, children| {
+            json!({
+                "name": name,
+                "detail": detail,
+                "kind": kind,
+                "range": self.range(span),
+                "selectionRange": self.range(selection),
+                "children": Value::Array(children),
+            })
+        };
+        let combinator = |decl: &CombinatorDecl, kind: i64| {
+            let fields = decl
+                .args
+                .iter()
+                .filter_map(|arg| match arg {
+                    Arg::Cond(a) => Some(symbol(
+                        a.ident.to_string(),
+                        a.term.to_string(),
+                        SYMBOL_FIELD,
+                        a.span,
+                        a.span,
+                        vec![],
+                    )),
+                    _ => None,
+                })
+                .collect();
+            symbol(
+                decl.identns.to_string(),
+                format!("#{:08x}", decl.get_id()),
+                kind,
+                decl.span,
+                decl.identns.span,
+                fields,
+            )
+        };
+
+        let builtins = self.schema.builtin.iter().map(|decl| {
+            symbol(
+                decl.identns.to_string(),
+                format!("#{:08x}", decl.get_id()),
+                SYMBOL_CLASS,
+                decl.span,
+                decl.identns.span,
+                vec![],
+            )
+        });
+        let constrs = self
+            .schema
+            .constrs
+            .iter()
+            .map(|d| combinator(d, SYMBOL_CONSTRUCTOR));
+        let funcs = self
+            .schema
+            .funcs
+            .iter()
+            .map(|d| combinator(d, SYMBOL_FUNCTION));
+
+        let mut symbols = builtins.chain(constrs).chain(funcs).collect::<Vec<_>>();
+        symbols.sort_by_key(|s| {
+            s.get("range")
+                .and_then(|r| r.get("start"))
+                .and_then(|p| p.get("line"))
+                .and_then(Value::as_i64)
+        });
+        Value::Array(symbols)
+    }
+}
+
+fn occurrences(schema: &TLSchema) -> Vec<Occurrence> {
+    let mut occurrences = vec![];
+    let mut push = |identns: &IdentNs, decl: bool| {
+        occurrences.push(Occurrence {
+            name: identns.to_string(),
+            span: identns.span,
+            decl,
+        })
+    };
+
+    for decl in &schema.builtin {
+        push(&decl.identns, true);
+        push(&decl.res, false);
+    }
+    for decl in schema.constrs.iter().chain(&schema.funcs) {
+        push(&decl.identns, true);
+        let mut idents = vec![];
+        for opt_arg in &decl.opt_args {
+            opt_arg
+                .terms
+                .iter()
+                .for_each(|t| term_idents(t, &mut idents));
+        }
+        decl.args.iter().for_each(|a| arg_idents(a, &mut idents));
+        idents.push(decl.res.identns());
+        match &decl.res {
+            ResType::Normal(r) => r.terms.iter().for_each(|t| term_idents(t, &mut idents)),
+            ResType::Ang(r) => std::iter::once(&r.term)
+                .chain(&r.terms)
+                .for_each(|t| term_idents(t, &mut idents)),
+        }
+        idents.into_iter().for_each(|i| push(i, false));
+    }
+    for decl in &schema.partial_apps {
+        push(&decl.identns, false);
+    }
+    for decl in &schema.finals {
+        push(&decl.identns, false);
+    }
+
+    occurrences.sort_by_key(|o| o.span.start);
+    occurrences
+}
+
+fn hover(index: &SchemaIndex, name: &str) -> Option<String> {
+    let (decl, module) = match index.decl(name) {
+        Some(Decl::Constructor(decl)) => (decl, "types"),
+        Some(Decl::Function(decl)) => (decl, "functions"),
+        Some(Decl::Builtin(decl)) => return Some(format!("```tl\n{}\n```", decl)),
+        None => return type_hover(index, name),
+    };
+    let mut hover = format!(
+        "```tl\n{}\n```\n\n{}\n\nRust: `{}::{}`",
+        decl,
+        id_line(decl),
+        module,
+        decl.identns.rust_path()
+    );
+    if let Some(description) = decl.docs.description() {
+        hover.push_str(&format!("\n\n{}", description));
+    }
+    Some(hover)
+}
+
+fn type_hover(index: &SchemaIndex, name: &str) -> Option<String> {
+    let constructors = index.constructors_of(name);
+    if constructors.is_empty() {
+        return None;
+    }
+    let identns = constructors[0].res.identns();
+    let list = constructors
+        .iter()
+        .map(|c| format!("- `{}#{:08x}`", c.identns, c.get_id()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(format!(
+        "type `{}`\n\nRust: `enums::{}`\n\nConstructors:\n{}",
+        name,
+        identns.rust_path(),
+        list
+    ))
+}
+
+fn definitions_of(schema: &TLSchema, index: &SchemaIndex, name: &str) -> Vec<Span> {
+    match index.decl(name) {
+        Some(Decl::Constructor(d) | Decl::Function(d)) => vec![d.identns.span],
+        Some(Decl::Builtin(d)) => vec![d.identns.span],
+        None => {
+            let builtins = schema
+                .builtin
+                .iter()
+                .filter(|b| b.res.to_string() == name)
+                .map(|b| b.identns.span);
+            index
+                .constructors_of(name)
+                .iter()
+                .map(|c| c.identns.span)
+                .chain(builtins)
+                .collect()
+        }
+    }
+}
+
+fn id_line(decl: &CombinatorDecl) -> String {
+    match decl.id {
+        Some(id) if id != decl.gen_id() => {
+            format!("ID: `#{:08x}` (computed: `#{:08x}`)", id, decl.gen_id())
+        }
+        _ => format!("ID: `#{:08x}`", decl.get_id()),
+    }
+}
+
+fn arg_idents<'s, 'a>(arg: &'s Arg<'a>, idents: &mut Vec<&'s IdentNs<'a>>) {
+    match arg {
+        Arg::Cond(a) => term_idents(&a.term, idents),
+        Arg::Single(a) => term_idents(&a.term, idents),
+        Arg::Par(a) => term_idents(&a.term, idents),
+        Arg::Brack(a) => a.args.iter().for_each(|a| arg_idents(a, idents)),
+    }
+}
+
+fn term_idents<'s, 'a>(term: &'s Term<'a>, idents: &mut Vec<&'s IdentNs<'a>>) {
+    match term {
+        Term::IdentNs(i) => idents.push(i),
+        Term::Ang(a) => {
+            idents.push(&a.identns);
+            std::iter::once(a.term.as_ref())
+                .chain(&a.terms)
+                .for_each(|t| term_idents(t, idents));
+        }
+        Term::Percent(t) | Term::Plus(t, ..) => term_idents(t, idents),
+        Term::Par(terms, _) => terms.iter().for_each(|t| term_idents(t, idents)),
+        Term::Nat(_) | Term::NatConst(..) => (),
+    }
+}
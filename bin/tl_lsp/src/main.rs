@@ -0,0 +1,17 @@
+//! Language server for `.tl` schema files, speaking LSP over stdio.
+//!
+//! It reports parse and validation errors, and offers hover, go-to-definition,
+//! find-references and document symbols within each open file.
+
+mod analysis;
+mod rpc;
+mod server;
+
+fn main() {
+    let stdin = std::io::stdin();
+    let code = server::run(stdin.lock(), std::io::stdout().lock()).unwrap_or_else(|e| {
+        eprintln!("tl_lsp: {}", e);
+        1
+    });
+    std::process::exit(code);
+}
@@ -0,0 +1,429 @@
+use crate::{
+    analysis::Document,
+    rpc::{error, notification, read_message, response, write_message},
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// The `TextDocumentSyncKind` of clients sending the whole text on every
+/// change.
+const FULL_SYNC: i64 = 1;
+
+/// The open documents, by URI. Each is parsed once per version, when the
+/// client sends it.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    exit: bool,
+}
+
+/// Serves messages from `reader` until the client sends `exit` or closes the
+/// input. Returns the process exit code.
+pub fn run<R: BufRead, W: Write>(mut reader: R, mut writer: W) -> io::Result<i32> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(&mut reader)? {
+        let replies = match serde_json::from_str::<Value>(&body) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![error(Value::Null, PARSE_ERROR, &e.to_string())],
+        };
+        for reply in &replies {
+            write_message(&mut writer, reply)?;
+        }
+        if server.exit {
+            break;
+        }
+    }
+    Ok(if server.shutdown { 0 } else { 1 })
+}
+
+impl Server {
+    /// Handles a request or notification and returns the messages to send
+    /// back.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message.get("method").and_then(Value::as_str);
+        let params = message.get("params").unwrap_or(&Value::Null);
+        let Some(id) = message.get("id").cloned() else {
+            return method.map_or(vec![], |m| self.notify(m, params));
+        };
+
+        let result = match method.unwrap_or_default() {
+            "initialize" => Some(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Some(Value::Null)
+            }
+            "textDocument/hover" => self.with_position(params, |doc, offset| {
+                let hover = doc
+                    .occurrence_at(offset)
+                    .and_then(|o| Some((doc.hover(&o.name)?, o)));
+                match hover {
+                    Some((markdown, o)) => json!({
+                        "contents": { "kind": "markdown", "value": markdown },
+                        "range": doc.range(o.span),
+                    }),
+                    None => Value::Null,
+                }
+            }),
+            "textDocument/definition" => self.with_position(params, |doc, offset| {
+                let spans = match doc.occurrence_at(offset) {
+                    Some(o) => doc.definitions(&o.name),
+                    None => &[],
+                };
+                locations(params, doc, spans)
+            }),
+            "textDocument/references" => self.with_position(params, |doc, offset| {
+                let include_decl = params
+                    .get("context")
+                    .and_then(|c| c.get("includeDeclaration"))
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+                let spans: Vec<_> = match doc.occurrence_at(offset) {
+                    Some(target) => doc
+                        .occurrences()
+                        .iter()
+                        .filter(|o| o.name == target.name && (include_decl || !o.decl))
+                        .map(|o| o.span)
+                        .collect(),
+                    None => vec![],
+                };
+                locations(params, doc, &spans)
+            }),
+            "textDocument/documentSymbol" => self.with_document(params, |doc| doc.symbols()),
+            _ => {
+                return vec![error(id, METHOD_NOT_FOUND, "method not found")];
+            }
+        };
+
+        match result {
+            Some(result) => vec![response(id, result)],
+            None => vec![error(id, INVALID_PARAMS, "unknown document or position")],
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let text_document = params.get("textDocument");
+        let uri = text_document
+            .and_then(|d| d.get("uri"))
+            .and_then(Value::as_str);
+        let version = text_document
+            .and_then(|d| d.get("version"))
+            .and_then(Value::as_i64);
+
+        match (method, uri) {
+            ("exit", _) => {
+                self.exit = true;
+                vec![]
+            }
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = text_document.and_then(|d| d.get("text"));
+                match text.and_then(Value::as_str) {
+                    Some(text) => self.update(uri, text, version),
+                    None => vec![],
+                }
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                // full sync: the last change holds the whole text
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Value::as_array)
+                    .and_then(|c| c.last())
+                    .and_then(|c| c.get("text"))
+                    .and_then(Value::as_str);
+                match text {
+                    Some(text) => self.update(uri, text, version),
+                    None => vec![],
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(uri);
+                vec![publish_diagnostics(uri, Value::Array(vec![]))]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Parses a new version of a document, unless it's the one already
+    /// parsed.
+    fn update(&mut self, uri: &str, text: &str, version: Option<i64>) -> Vec<Value> {
+        let current = self.documents.get(uri);
+        if version.is_none() || current.map(|d| d.version) != Some(version) {
+            let doc = Document::new(text.to_string(), version);
+            self.documents.insert(uri.to_string(), doc);
+        }
+        let diagnostics = self.documents[uri].lsp_diagnostics();
+        vec![publish_diagnostics(uri, diagnostics)]
+    }
+
+    fn with_document(&self, params: &Value, f: impl FnOnce(&Document) -> Value) -> Option<Value> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        Some(f(self.documents.get(uri)?))
+    }
+
+    fn with_position(
+        &self,
+        params: &Value,
+        f: impl FnOnce(&Document, usize) -> Value,
+    ) -> Option<Value> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let doc = self.documents.get(uri)?;
+        let offset = doc.offset(params.get("position")?)?;
+        Some(f(doc, offset))
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": FULL_SYNC,
+            "hoverProvider": true,
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "documentSymbolProvider": true,
+        },
+        "serverInfo": { "name": "tl_lsp" },
+    })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Value) -> Value {
+    notification(
+        "textDocument/publishDiagnostics",
+        json!({ "uri": uri, "diagnostics": diagnostics }),
+    )
+}
+
+fn locations(params: &Value, doc: &Document, spans: &[parser::span::Span]) -> Value {
+    let uri = params
+        .get("textDocument")
+        .and_then(|d| d.get("uri"))
+        .cloned()
+        .unwrap_or(Value::Null);
+    let locations = spans
+        .iter()
+        .map(|&span| json!({ "uri": uri, "range": doc.range(span) }))
+        .collect();
+    Value::Array(locations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use crate::rpc::read_message;
+    use serde_json::{json, Value};
+    use std::io::{BufReader, Cursor};
+
+    const URI: &str = "file:///schema.tl";
+    const SCHEMA: &str = "int ? = Int;
+vector {t:Type} # [ t ] = Vector t;
+// @description A user
+user#12345678 id:int = User;
+userEmpty#2 id:int = User;
+chat#3 creator:User admins:Vector<User> = Chat;
+---functions---
+users.getUsers#4 id:Vector<int> = Vector<User>;
+";
+
+    fn frame(messages: &[String]) -> Vec<u8> {
+        messages
+            .iter()
+            .flat_map(|m| format!("Content-Length: {}\r\n\r\n{}", m.len(), m).into_bytes())
+            .collect()
+    }
+
+    fn request(id: i64, method: &str, params: &str) -> String {
+        format!(
+            r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+            id, method, params
+        )
+    }
+
+    fn at(line: u32, character: u32) -> String {
+        format!(
+            r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}},"context":{{"includeDeclaration":false}}}}"#,
+            URI, line, character
+        )
+    }
+
+    /// Runs a scripted session and returns the replies to each request id,
+    /// plus the diagnostics published.
+    fn session(text: &str, requests: &[String]) -> (Vec<Value>, Vec<Value>, i32) {
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "version": 1, "text": text } },
+        });
+        let mut messages = vec![request(0, "initialize", "{}"), open.to_string()];
+        messages.extend(requests.iter().cloned());
+        messages.push(request(99, "shutdown", "null"));
+        messages.push(r#"{"jsonrpc":"2.0","method":"exit"}"#.to_string());
+
+        let mut output = vec![];
+        let code = run(Cursor::new(frame(&messages)), &mut output).unwrap();
+
+        let mut reader = BufReader::new(output.as_slice());
+        let mut replies = vec![];
+        let mut diagnostics = vec![];
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            let message = serde_json::from_str::<Value>(&body).unwrap();
+            match message.get("method").and_then(Value::as_str) {
+                Some("textDocument/publishDiagnostics") => {
+                    diagnostics.push(message.get("params").unwrap().clone())
+                }
+                _ => replies.push(message),
+            }
+        }
+        (replies, diagnostics, code)
+    }
+
+    fn result(replies: &[Value], id: i64) -> &Value {
+        replies
+            .iter()
+            .find(|r| r.get("id").and_then(Value::as_i64) == Some(id))
+            .and_then(|r| r.get("result"))
+            .unwrap()
+    }
+
+    fn lines(locations: &Value) -> Vec<(i64, i64)> {
+        locations
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| {
+                let start = l.get("range").unwrap().get("start").unwrap();
+                (
+                    start.get("line").unwrap().as_i64().unwrap(),
+                    start.get("character").unwrap().as_i64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_session() {
+        let (replies, diagnostics, code) = session(
+            SCHEMA,
+            &[
+                request(1, "textDocument/hover", &at(3, 2)),
+                request(2, "textDocument/hover", &at(5, 16)),
+                request(3, "textDocument/definition", &at(5, 16)),
+                request(4, "textDocument/references", &at(3, 25)),
+                request(5, "textDocument/documentSymbol", &at(0, 0)),
+                request(6, "textDocument/hover", &at(6, 0)),
+                request(7, "textDocument/formatting", &at(0, 0)),
+            ],
+        );
+        assert_eq!(code, 0);
+
+        let capabilities = result(&replies, 0).get("capabilities").unwrap();
+        assert_eq!(capabilities.get("hoverProvider"), Some(&true.into()));
+        assert_eq!(diagnostics, [json!({ "uri": URI, "diagnostics": [] })]);
+
+        let hover = result(&replies, 1).get("contents").unwrap().get("value");
+        assert_eq!(
+            hover.and_then(Value::as_str),
+            Some(
+                "```tl\nuser#12345678 id:int = User;\n```\n\n\
+                 ID: `#12345678` (computed: `#1ec2365e`)\n\nRust: `types::User`\n\nA user"
+            )
+        );
+        let hover = result(&replies, 2).get("contents").unwrap().get("value");
+        assert_eq!(
+            hover.and_then(Value::as_str),
+            Some(
+                "type `User`\n\nRust: `enums::User`\n\nConstructors:\n\
+                 - `user#12345678`\n- `userEmpty#00000002`"
+            )
+        );
+
+        assert_eq!(lines(result(&replies, 3)), [(3, 0), (4, 0)]);
+        assert_eq!(
+            lines(result(&replies, 4)),
+            [(3, 23), (4, 21), (5, 15), (5, 34), (7, 41)]
+        );
+
+        let symbols = result(&replies, 5).as_array().unwrap();
+        let names = symbols
+            .iter()
+            .map(|s| s.get("name").and_then(Value::as_str).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "int",
+                "vector",
+                "user",
+                "userEmpty",
+                "chat",
+                "users.getUsers"
+            ]
+        );
+        let fields = symbols[4].get("children").unwrap().as_array().unwrap();
+        assert_eq!(fields[1].get("name"), Some(&"admins".into()));
+        assert_eq!(fields[1].get("detail"), Some(&"Vector<User>".into()));
+
+        assert_eq!(result(&replies, 6), &Value::Null);
+        let error = replies
+            .iter()
+            .find(|r| r.get("id").and_then(Value::as_i64) == Some(7))
+            .and_then(|r| r.get("error"))
+            .and_then(|e| e.get("code"));
+        assert_eq!(error, Some(&(-32601i64).into()));
+    }
+
+    #[test]
+    fn test_did_change() {
+        let change = |version: i64, text: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": version },
+                    "contentChanges": [{ "text": text }],
+                },
+            })
+            .to_string()
+        };
+        let (_, diagnostics, _) = session(
+            SCHEMA,
+            &[
+                change(2, "user#1 id:int = ;\n"),
+                // the same version again is not parsed again
+                change(2, SCHEMA),
+                change(3, SCHEMA),
+            ],
+        );
+        let counts = diagnostics
+            .iter()
+            .map(|d| d.get("diagnostics").unwrap().as_array().unwrap().len())
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let text = "user#1 id:int = User;\nchat#2 id:int = ;\nmsg#3 from:Peer = Message;\n";
+        let (_, diagnostics, _) = session(text, &[]);
+        let diagnostics = diagnostics[0].get("diagnostics").unwrap();
+        let messages = diagnostics
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d.get("message").and_then(Value::as_str).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, ["expected result type"]);
+        assert_eq!(lines(diagnostics), [(1, 16)]);
+
+        let text = "int ? = Int;\nmsg#3 id:int from:Peer = Message;\n";
+        let (_, diagnostics, _) = session(text, &[]);
+        let diagnostics = diagnostics[0].get("diagnostics").unwrap();
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(lines(diagnostics), [(1, 18)]);
+    }
+}
@@ -1,4 +1,5 @@
 use parser::combinators::schema;
+use parser::index::Decl;
 use parser::types::TLSchema;
 use std::collections::HashMap;
 use std::fs::File;
@@ -19,20 +20,122 @@ fn count_arg(schema: &TLSchema, counter: &mut HashMap<&str, i32>) {
     }
 }
 
+fn verify_ids(name: &str, schema: &TLSchema) -> bool {
+    let mismatches = schema.verify_ids();
+    for mismatch in &mismatches {
+        println!("{}: {}", name, mismatch);
+    }
+    mismatches.is_empty()
+}
+
+/// Prints the differences between two schema files, as text or with
+/// `--json` as JSON.
+fn diff(paths: &[String], json: bool) {
+    let [old, new] = paths else {
+        eprintln!("usage: tl_stats --diff <old.tl> <new.tl> [--json]");
+        std::process::exit(2);
+    };
+    let load = |path: &str| {
+        TLSchema::from_path(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    };
+
+    let diff = load(old).diff(&load(new));
+    if json {
+        println!("{:#}", diff.to_json());
+    } else {
+        print!("{}", diff);
+    }
+}
+
+/// Looks up a declaration by name or `#id` and a type by name in the
+/// bundled schemas and prints what uses them.
+fn query(what: &str) {
+    let schema = TLSchema::from_paths(["data/schema/api.tl", "data/schema/mtproto.tl"])
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let index = schema.index();
+
+    let decl = match what.strip_prefix('#') {
+        Some(id) => u32::from_str_radix(id, 16).ok().and_then(|id| {
+            index
+                .constructor(id)
+                .map(Decl::Constructor)
+                .or(index.function(id).map(Decl::Function))
+        }),
+        None => index.decl(what),
+    };
+    match decl {
+        Some(Decl::Constructor(d)) => println!("constructor {}", d),
+        Some(Decl::Function(d)) => println!("function {}", d),
+        Some(Decl::Builtin(d)) => println!("builtin {}", d),
+        None => (),
+    }
+
+    let sections = [
+        ("constructors", index.constructors_of(what)),
+        ("returned by", index.functions_returning(what)),
+        ("referenced by", index.referenced_by(what)),
+    ];
+    for (title, decls) in sections {
+        if !decls.is_empty() {
+            println!("{}:", title);
+            for decl in decls {
+                println!("  {}", decl.identns);
+            }
+        }
+    }
+}
+
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().is_some_and(|a| a == "--query") {
+        match args.get(1) {
+            Some(what) => query(what),
+            None => {
+                eprintln!("usage: tl_stats --query <name|#id>");
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+    if args.first().is_some_and(|a| a == "--diff") {
+        let json = args.iter().any(|a| a == "--json");
+        let paths = args[1..]
+            .iter()
+            .filter(|a| *a != "--json")
+            .cloned()
+            .collect::<Vec<_>>();
+        diff(&paths, json);
+        return;
+    }
+
     let mut api_schema_text = String::new();
     File::open("data/schema/api.tl")
         .unwrap()
         .read_to_string(&mut api_schema_text)
         .unwrap();
-    let api_schema = schema(&api_schema_text).unwrap().1;
+    let api_schema = schema(&api_schema_text).unwrap();
 
     let mut mtproto_schema_text = String::new();
     File::open("data/schema/mtproto.tl")
         .unwrap()
         .read_to_string(&mut mtproto_schema_text)
         .unwrap();
-    let mtproto_schema = schema(&mtproto_schema_text).unwrap().1;
+    let mtproto_schema = schema(&mtproto_schema_text).unwrap();
+
+    if std::env::args().any(|a| a == "--verify-ids") {
+        let api_ok = verify_ids("api.tl", &api_schema);
+        let mtproto_ok = verify_ids("mtproto.tl", &mtproto_schema);
+        if !(api_ok && mtproto_ok) {
+            std::process::exit(1);
+        }
+        return;
+    }
 
     let mut counter = HashMap::new();
 
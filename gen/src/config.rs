@@ -0,0 +1,131 @@
+/// What [`generate_code`](crate::generate::generate_code) writes, and how.
+/// The defaults generate everything, as the `raw` crate needs it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenConfig {
+    derives: Vec<String>,
+    namespaces: Option<Vec<String>>,
+    root: String,
+    docs: bool,
+    deserialize: bool,
+    functions: bool,
+    feature_gates: bool,
+    require_layer: bool,
+}
+
+impl Default for GenConfig {
+    fn default() -> Self {
+        Self {
+            derives: vec!["Debug".into(), "Clone".into(), "PartialEq".into()],
+            namespaces: None,
+            root: "crate".into(),
+            docs: true,
+            deserialize: true,
+            functions: true,
+            feature_gates: false,
+            require_layer: false,
+        }
+    }
+}
+
+impl GenConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Derives `name` on every generated type, besides `Debug`, `Clone` and
+    /// `PartialEq`.
+    pub fn derive(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        if !self.derives.contains(&name) {
+            self.derives.push(name);
+        }
+        self
+    }
+
+    /// Only generates the declarations in these namespaces, and those without
+    /// one. Namespaces they refer to must be listed too.
+    pub fn namespaces<I, S>(mut self, namespaces: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.namespaces = Some(namespaces.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// The path of the module the code is included in, `crate` by default.
+    /// Besides the generated modules, it must provide the items of the `raw`
+    /// crate root, like `Serializable` and `RawVec`.
+    pub fn root(mut self, path: impl Into<String>) -> Self {
+        self.root = path.into();
+        self
+    }
+
+    /// Whether the schema's `//@description` comments become doc comments.
+    pub fn docs(mut self, docs: bool) -> Self {
+        self.docs = docs;
+        self
+    }
+
+    /// Whether types implement `Deserializable`.
+    pub fn deserialize(mut self, deserialize: bool) -> Self {
+        self.deserialize = deserialize;
+        self
+    }
+
+    /// Whether the `functions` module is generated.
+    pub fn functions(mut self, functions: bool) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    /// Whether namespace modules are only compiled with the cargo feature of
+    /// the same name. See [`Features`](crate::features::Features).
+    pub fn feature_gates(mut self, feature_gates: bool) -> Self {
+        self.feature_gates = feature_gates;
+        self
+    }
+
+    /// Whether a schema without a `// LAYER N` comment is an error. Otherwise
+    /// the `LAYER` constant is only generated when the schema names a layer.
+    pub fn require_layer(mut self, require_layer: bool) -> Self {
+        self.require_layer = require_layer;
+        self
+    }
+
+    /// The traits to derive, as written in `#[derive(...)]`.
+    pub fn derives(&self) -> String {
+        self.derives.join(", ")
+    }
+
+    pub fn includes_namespace(&self, ns: Option<&str>) -> bool {
+        match (&self.namespaces, ns) {
+            (Some(namespaces), Some(ns)) => namespaces.iter().any(|n| n == ns),
+            _ => true,
+        }
+    }
+
+    pub fn root_path(&self) -> &str {
+        &self.root
+    }
+
+    pub fn emit_docs(&self) -> bool {
+        self.docs
+    }
+
+    pub fn emit_deserialize(&self) -> bool {
+        self.deserialize
+    }
+
+    pub fn emit_functions(&self) -> bool {
+        self.functions
+    }
+
+    pub fn emit_feature_gates(&self) -> bool {
+        self.feature_gates
+    }
+
+    pub fn requires_layer(&self) -> bool {
+        self.require_layer
+    }
+}
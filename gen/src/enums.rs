@@ -1,42 +1,56 @@
-use crate::{formatter::Rustifiable, metadata::Metadata, structs::get_struct_field_type};
-use parser::types::{Arg, CombinatorDecl, IdentNs, ResType};
-use std::{
-    collections::HashSet,
-    io::{self, Write},
+use crate::{
+    config::GenConfig,
+    formatter::Rustifiable,
+    generate::write_module,
+    ir::{BoxedType, Constructor, Schema, Type},
+    metadata::Metadata,
 };
+use std::io::{self, Write};
 
-fn get_enum_variant_path(identns: &IdentNs) -> String {
+fn get_enum_variant_path(config: &GenConfig, constr: &Constructor) -> String {
     format!(
-        "crate::{}::{}",
-        if identns.is_boxed() { "enums" } else { "types" },
-        identns.rust_path()
+        "{}::types::{}",
+        config.root_path(),
+        constr.decl.identns.rust_path()
     )
 }
 
-fn is_recursive_enum_variant(enum_name: &str, args: &[Arg]) -> bool {
-    args.iter()
-        .map(get_struct_field_type)
-        .any(|a| a == format!("crate::enums::{}", enum_name))
+/// The type wrapped by a variant, boxed if it contains its own enum.
+fn get_enum_variant_type(config: &GenConfig, constr: &Constructor) -> String {
+    let path = get_enum_variant_path(config, constr);
+    if is_recursive_enum_variant(constr) {
+        format!("Box<{}>", path)
+    } else {
+        path
+    }
+}
+
+fn is_recursive_enum_variant(constr: &Constructor) -> bool {
+    constr
+        .fields
+        .iter()
+        .any(|f| f.flag.is_none() && f.ty == Type::Boxed(constr.ty))
 }
 
 fn write_enum<W: Write>(
     f: &mut W,
-    res: &ResType,
-    decls: &HashSet<&CombinatorDecl>,
+    schema: &Schema,
+    config: &GenConfig,
+    ty: &BoxedType,
     indent: &str,
 ) -> io::Result<()> {
-    let enum_name = res.rust_name();
+    let enum_name = ty.res.rust_name();
 
+    writeln!(f, "{indent}#[derive({})]", config.derives())?;
     writeln!(f, "{indent}pub enum {enum_name} {{")?;
 
-    for decl in decls {
-        let mut ty = get_enum_variant_path(&decl.identns);
-
-        if is_recursive_enum_variant(&enum_name, &decl.args) {
-            ty = format!("Box<{}>", ty);
-        }
-
-        writeln!(f, "{indent}    {}({}),", decl.identns.rust_name(), ty)?;
+    for constr in schema.constructors_of(ty) {
+        writeln!(
+            f,
+            "{indent}    {}({}),",
+            constr.decl.identns.rust_name(),
+            get_enum_variant_type(config, constr)
+        )?;
     }
     writeln!(f, "{indent}}}")?;
 
@@ -45,25 +59,27 @@ fn write_enum<W: Write>(
 
 fn write_enum_ser<W: Write>(
     f: &mut W,
-    res: &ResType,
-    decls: &HashSet<&CombinatorDecl>,
+    schema: &Schema,
+    config: &GenConfig,
+    ty: &BoxedType,
     indent: &str,
 ) -> io::Result<()> {
-    let enum_name = res.rust_name();
+    let root = config.root_path();
+    let enum_name = ty.res.rust_name();
 
-    writeln!(f, "{indent}impl crate::Serializable for {enum_name} {{")?;
+    writeln!(f, "{indent}impl {root}::Serializable for {enum_name} {{")?;
     writeln!(
         f,
-        "{indent}    fn serialize(&self, buf: crate::serialize::Buf) {{"
+        "{indent}    fn serialize(&self, buf: {root}::serialize::Buf) {{"
     )?;
 
-    writeln!(f, "{indent}        use crate::Identificable;")?;
+    writeln!(f, "{indent}        use {root}::Identificable;")?;
     writeln!(f, "{indent}        match self {{")?;
-    for decl in decls {
-        let variant_name = decl.identns.rust_name();
-        let variant_path = get_enum_variant_path(&decl.identns);
+    for constr in schema.constructors_of(ty) {
+        let variant_name = constr.decl.identns.rust_name();
+        let variant_path = get_enum_variant_path(config, constr);
 
-        let anon_name = if !decl.args.is_empty() { "x" } else { "_" };
+        let anon_name = if !constr.fields.is_empty() { "x" } else { "_" };
 
         writeln!(
             f,
@@ -73,7 +89,7 @@ fn write_enum_ser<W: Write>(
             f,
             "{indent}                {variant_path}::ID.serialize(buf);"
         )?;
-        if !decl.args.is_empty() {
+        if !constr.fields.is_empty() {
             writeln!(f, "{indent}                {anon_name}.serialize(buf);")?;
         }
         writeln!(f, "{indent}            }}")?;
@@ -87,50 +103,75 @@ fn write_enum_ser<W: Write>(
 
 fn write_enum_des<W: Write>(
     f: &mut W,
-    res: &ResType,
-    decls: &HashSet<&CombinatorDecl>,
+    schema: &Schema,
+    config: &GenConfig,
+    ty: &BoxedType,
     indent: &str,
 ) -> io::Result<()> {
-    let enum_name = res.rust_name();
+    let root = config.root_path();
+    let enum_name = ty.res.rust_name();
+
+    writeln!(f, "{indent}impl {root}::Deserializable for {enum_name} {{")?;
+    writeln!(
+        f,
+        "{indent}    fn deserialize(buf: {root}::deserialize::Buf) -> {root}::Result<Self> {{"
+    )?;
+    writeln!(
+        f,
+        "{indent}        let id = <u32 as {root}::Deserializable>::deserialize(buf)?;"
+    )?;
+    writeln!(f, "{indent}        Ok(match id {{")?;
+    for constr in schema.constructors_of(ty) {
+        let variant_name = constr.decl.identns.rust_name();
+        let variant_type = get_enum_variant_type(config, constr);
 
-    writeln!(f, "{indent}impl crate::Deserializable for {enum_name} {{")?;
+        writeln!(
+            f,
+            "{indent}            {:#010x} => Self::{variant_name}(<{variant_type} as {root}::Deserializable>::deserialize(buf)?),",
+            constr.id
+        )?;
+    }
     writeln!(
         f,
-        "{indent}    fn deserialize(buf: crate::deserialize::Buf) -> crate::Result<Self> {{"
+        "{indent}            _ => return Err({root}::Err::UnexpectedConstructor {{ id, ty: {:?} }}),",
+        ty.name.to_string()
     )?;
+    writeln!(f, "{indent}        }})")?;
     writeln!(f, "{indent}    }}")?;
     writeln!(f, "{indent}}}")?;
     Ok(())
 }
 
-pub fn write_enums<W: Write>(f: &mut W, metadata: &Metadata) -> io::Result<()> {
-    writeln!(f, "pub mod enums {{")?;
-    for (ns, groups) in &metadata.types_group_ns {
-        let indent = if let Some(ns) = ns {
-            writeln!(f, "    pub mod {ns} {{")?;
-            "        "
-        } else {
-            "    "
-        };
-
-        for (res_ty, decls) in groups {
-            if let ResType::Normal(n) = res_ty {
-                match n.identns.name {
-                    "Int128" | "Int256" | "Vector" => continue,
-                    _ => (),
-                }
-            }
-            writeln!(f, "{indent}#[derive(Debug, Clone, PartialEq)]")?;
-            write_enum(f, res_ty, decls, indent)?;
-            //write_enum_des(f, decls, indent)?;
-            write_enum_ser(f, res_ty, decls, indent)?;
-        }
-
-        if ns.is_some() {
-            writeln!(f, "    }}")?;
+/// Writes the enums of the boxed types in one namespace.
+pub(crate) fn write_enum_items<W: Write>(
+    f: &mut W,
+    schema: &Schema,
+    config: &GenConfig,
+    types: &[&BoxedType],
+    indent: &str,
+) -> io::Result<()> {
+    for ty in types {
+        write_enum(f, schema, config, ty, indent)?;
+        if config.emit_deserialize() {
+            write_enum_des(f, schema, config, ty, indent)?;
         }
+        write_enum_ser(f, schema, config, ty, indent)?;
     }
-    writeln!(f, "}}")?;
-
     Ok(())
 }
+
+pub fn write_enums<W: Write>(
+    f: &mut W,
+    schema: &Schema,
+    config: &GenConfig,
+    metadata: &Metadata,
+) -> io::Result<()> {
+    write_module(
+        f,
+        config,
+        metadata,
+        "enums",
+        &metadata.types_group_ns,
+        |f, types, indent| write_enum_items(f, schema, config, types, indent),
+    )
+}
@@ -0,0 +1,188 @@
+use crate::ir::{Field, Schema, Type};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Cargo features gating the namespace modules, so a crate only compiles
+/// the namespaces it uses.
+///
+/// The feature of a namespace, like `messages`, gates its `types` and
+/// `enums` modules and enables the namespaces their types refer to. Its
+/// `functions` module has a feature of its own, `functions-messages`, which
+/// enables the namespaces of the types the functions take and return.
+///
+/// Declarations without a namespace are always compiled, and so are the
+/// namespaces they refer to, directly or not, which get no feature. For
+/// api.tl these are `messages` and `updates`, but not their functions.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Features<'a> {
+    /// Namespaces the root one needs.
+    always: BTreeSet<&'a str>,
+    /// The namespaces the types of each gated one refer to, gated or not.
+    deps: BTreeMap<&'a str, BTreeSet<&'a str>>,
+    /// The namespaces the functions of each namespaced one refer to.
+    function_deps: BTreeMap<&'a str, BTreeSet<&'a str>>,
+}
+
+impl<'a> Features<'a> {
+    pub fn new(schema: &'a Schema<'a>) -> Self {
+        let mut deps = BTreeMap::<Option<&str>, BTreeSet<&str>>::new();
+        let mut add = |ns: Option<&'a str>, refs: BTreeSet<&'a str>| {
+            let entry = deps.entry(ns).or_default();
+            entry.extend(refs.into_iter().filter(|r| Some(*r) != ns));
+        };
+
+        for constr in &schema.constructors {
+            let ns = constr.decl.identns.namespace.as_deref();
+            add(ns, field_namespaces(schema, &constr.fields));
+        }
+        for ty in &schema.types {
+            let refs = schema
+                .constructors_of(ty)
+                .filter_map(|c| c.decl.identns.namespace.as_deref())
+                .collect();
+            add(ty.name.namespace.as_deref(), refs);
+        }
+
+        let mut function_deps = BTreeMap::<Option<&str>, BTreeSet<&str>>::new();
+        for func in &schema.functions {
+            let mut refs = field_namespaces(schema, &func.fields);
+            type_namespaces(schema, &func.ret, &mut refs);
+            let ns = func.decl.identns.namespace.as_deref();
+            function_deps.entry(ns).or_default().extend(refs);
+        }
+
+        // everything reachable from the root namespace can't be turned off
+        let mut always = BTreeSet::new();
+        let mut pending = deps
+            .get(&None)
+            .into_iter()
+            .chain(function_deps.get(&None))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        while let Some(ns) = pending.pop() {
+            if always.insert(ns) {
+                pending.extend(deps.get(&Some(ns)).into_iter().flatten().copied());
+            }
+        }
+
+        let deps = deps
+            .into_iter()
+            .filter_map(|(ns, refs)| ns.map(|ns| (ns, refs)))
+            .filter(|(ns, _)| !always.contains(ns))
+            .collect();
+        let function_deps = function_deps
+            .into_iter()
+            .filter_map(|(ns, refs)| ns.map(|ns| (ns, refs)))
+            .collect();
+        Self {
+            always,
+            deps,
+            function_deps,
+        }
+    }
+
+    /// The feature that compiles namespace `ns` of `module`, if it has one.
+    pub fn gate(&self, module: &str, ns: &str) -> Option<String> {
+        match module {
+            "functions" if self.function_deps.contains_key(ns) => Some(format!("functions-{}", ns)),
+            "functions" => None,
+            _ if self.deps.contains_key(ns) => Some(ns.to_string()),
+            _ => None,
+        }
+    }
+
+    /// The namespaces that are always compiled, because declarations
+    /// without one refer to them.
+    pub fn always(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.always.iter().copied()
+    }
+
+    /// The `[features]` table of a crate including the generated code. Every
+    /// namespace is enabled by default, through `all`.
+    pub fn cargo_table(&self) -> String {
+        let list = |names: Vec<String>| {
+            names
+                .iter()
+                .map(|n| format!("\"{}\"", n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let gated = |refs: &BTreeSet<&str>| {
+            refs.iter()
+                .filter(|r| self.deps.contains_key(*r))
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut table = String::from("[features]\n");
+        table.push_str(&comment(&format!(
+            "The types of these namespaces are always compiled, because declarations \
+             without a namespace refer to them: {}.",
+            self.always().collect::<Vec<_>>().join(", ")
+        )));
+        table.push_str("default = [\"all\"]\n");
+
+        let features = self.deps.keys().map(|ns| ns.to_string());
+        let functions = self
+            .function_deps
+            .keys()
+            .map(|ns| format!("functions-{}", ns));
+        table.push_str(&format!(
+            "all = [{}]\n",
+            list(features.chain(functions).collect())
+        ));
+        for (ns, refs) in &self.deps {
+            let cycle = refs
+                .iter()
+                .filter(|r| self.deps.get(*r).is_some_and(|back| back.contains(ns)))
+                .collect::<Vec<_>>();
+            if let Some(other) = cycle.iter().find(|r| **r > ns) {
+                table.push_str(&comment(&format!(
+                    "`{}` and `{}` refer to each other, so enabling either enables both.",
+                    ns, other
+                )));
+            }
+            table.push_str(&format!("{} = [{}]\n", ns, list(gated(refs))));
+        }
+        for (ns, refs) in &self.function_deps {
+            table.push_str(&format!("functions-{} = [{}]\n", ns, list(gated(refs))));
+        }
+        table
+    }
+}
+
+/// `text` as TOML comment lines, wrapped to 80 columns.
+fn comment(text: &str) -> String {
+    let mut comment = String::new();
+    let mut line = String::from("#");
+    for word in text.split_whitespace() {
+        if line.len() + 1 + word.len() > 80 {
+            comment.push_str(&line);
+            comment.push('\n');
+            line = String::from("#");
+        }
+        line.push(' ');
+        line.push_str(word);
+    }
+    comment.push_str(&line);
+    comment.push('\n');
+    comment
+}
+
+fn field_namespaces<'a>(schema: &'a Schema, fields: &[Field]) -> BTreeSet<&'a str> {
+    let mut refs = BTreeSet::new();
+    fields
+        .iter()
+        .for_each(|f| type_namespaces(schema, &f.ty, &mut refs));
+    refs
+}
+
+fn type_namespaces<'a>(schema: &'a Schema, ty: &Type, refs: &mut BTreeSet<&'a str>) {
+    let ns = match ty {
+        Type::Bare(i) => schema.constructors[*i].decl.identns.namespace.as_deref(),
+        Type::Boxed(i) => schema.types[*i].name.namespace.as_deref(),
+        Type::Vector(ty) | Type::BareVector(ty) => return type_namespaces(schema, ty, refs),
+        _ => None,
+    };
+    refs.extend(ns);
+}
@@ -1,3 +1,4 @@
+use crate::ir::Builtin;
 use parser::types::{IdentNs, ResType};
 
 pub trait Rustifiable {
@@ -18,19 +19,16 @@ pub fn escape_builtin_kw(ty: &str) -> String {
     }
 }
 
-pub fn builtin_type(ty: &str) -> Option<&str> {
-    Some(match ty {
-        "int" => "i32",
-        "long" => "i64",
-        "double" => "f64",
-        "string" => "String",
-        "bytes" => "Vec<u8>",
-        "int128" => "crate::Int128",
-        "int256" => "crate::Int256",
-        "vector" => "crate::RawVec",
-        "Vector" => "Vec",
-        _ => return None,
-    })
+pub fn builtin_type(ty: Builtin, root: &str) -> String {
+    match ty {
+        Builtin::Int => "i32".to_string(),
+        Builtin::Long => "i64".to_string(),
+        Builtin::Double => "f64".to_string(),
+        Builtin::String => "String".to_string(),
+        Builtin::Bytes => "Vec<u8>".to_string(),
+        Builtin::Int128 => format!("{root}::Int128"),
+        Builtin::Int256 => format!("{root}::Int256"),
+    }
 }
 
 impl<'a> Rustifiable for IdentNs<'a> {
@@ -76,7 +74,7 @@ impl<'a> Rustifiable for IdentNs<'a> {
     }
 
     fn rust_path(&self) -> String {
-        match self.namespace {
+        match &self.namespace {
             Some(n) => format!("{}::{}", n, self.rust_name()),
             None => self.rust_name(),
         }
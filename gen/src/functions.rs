@@ -0,0 +1,99 @@
+use crate::{
+    config::GenConfig,
+    formatter::Rustifiable,
+    generate::write_module,
+    ir::{Function, Schema, Type},
+    metadata::Metadata,
+    structs::{get_generics, rust_type, write_fields_ser, write_struct},
+};
+use std::io::{self, Write};
+
+/// What a function returns. Wrappers like `invokeWithLayer`, which return
+/// their query's type `X`, respond with whatever `X` does.
+fn response_type(schema: &Schema, config: &GenConfig, ty: &Type) -> String {
+    match ty {
+        Type::Generic(name) => format!("{name}::Response"),
+        Type::Vector(ty) => format!("Vec<{}>", response_type(schema, config, ty)),
+        Type::BareVector(ty) => format!(
+            "{}::RawVec<{}>",
+            config.root_path(),
+            response_type(schema, config, ty)
+        ),
+        ty => rust_type(schema, config, ty),
+    }
+}
+
+fn write_function_ser<W: Write>(
+    f: &mut W,
+    config: &GenConfig,
+    func: &Function,
+    indent: &str,
+) -> io::Result<()> {
+    let root = config.root_path();
+    let struct_name = func.decl.identns.rust_name();
+    let (params, args) = get_generics(config, &func.fields);
+
+    writeln!(
+        f,
+        "{indent}impl{params} {root}::Serializable for {struct_name}{args} {{"
+    )?;
+    writeln!(
+        f,
+        "{indent}    fn serialize(&self, buf: {root}::serialize::Buf) {{"
+    )?;
+    writeln!(f, "{indent}        use {root}::Identificable;")?;
+    writeln!(f, "{indent}        Self::ID.serialize(buf);")?;
+    write_fields_ser(f, &func.fields, &format!("{indent}        "))?;
+    writeln!(f, "{indent}    }}")?;
+    writeln!(f, "{indent}}}")?;
+    Ok(())
+}
+
+fn write_request<W: Write>(
+    f: &mut W,
+    schema: &Schema,
+    config: &GenConfig,
+    func: &Function,
+    indent: &str,
+) -> io::Result<()> {
+    let root = config.root_path();
+    let (params, args) = get_generics(config, &func.fields);
+    writeln!(
+        f,
+        "{indent}impl{params} {root}::Request for {}{args} {{ type Response = {}; }}",
+        func.decl.identns.rust_name(),
+        response_type(schema, config, &func.ret)
+    )
+}
+
+/// Writes the structs of the functions in one namespace.
+pub(crate) fn write_function_items<W: Write>(
+    f: &mut W,
+    schema: &Schema,
+    config: &GenConfig,
+    funcs: &[&Function],
+    indent: &str,
+) -> io::Result<()> {
+    for func in funcs {
+        write_struct(f, schema, config, func.decl, func.id, &func.fields, indent)?;
+        write_function_ser(f, config, func, indent)?;
+        write_request(f, schema, config, func, indent)?;
+    }
+    Ok(())
+}
+
+pub fn write_functions<W: Write>(
+    f: &mut W,
+    schema: &Schema,
+    config: &GenConfig,
+    metadata: &Metadata,
+) -> io::Result<()> {
+    write_module(
+        f,
+        config,
+        metadata,
+        "functions",
+        &metadata.funcs_ns,
+        |f, funcs, indent| write_function_items(f, schema, config, funcs, indent),
+    )
+}
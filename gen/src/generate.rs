@@ -1,20 +1,265 @@
-use crate::{enums::write_enums, metadata::Metadata, structs::write_structs};
+use crate::{
+    config::GenConfig,
+    enums::{write_enum_items, write_enums},
+    functions::{write_function_items, write_functions},
+    ir::Schema,
+    metadata::Metadata,
+    structs::{write_struct_items, write_structs},
+};
 use parser::types::TLSchema;
-use std::io::{self, Write};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
 
-pub fn generate_code<W: Write>(f: &mut W, schema: TLSchema, layer: u32) -> io::Result<()> {
-    let meta = Metadata::new(&schema);
-    writeln!(f, "pub const LAYER: u32 = {};", layer)?;
-    
-    writeln!(f, "#[allow(clippy::large_enum_variant, non_snake_case)]")?;
-    write_structs(f, &meta)?;
-    writeln!(f, "#[allow(clippy::large_enum_variant, non_snake_case)]")?;
-    write_enums(f, &meta)?;
+const ALLOW: &str = "#[allow(clippy::large_enum_variant, non_snake_case)]";
 
-    writeln!(f, "#[allow(clippy::large_enum_variant, non_snake_case)]")?;
-    writeln!(f, "pub mod functions {{")?;
-    writeln!(f, "}}")?;
+pub fn generate_code<W: Write>(f: &mut W, schema: TLSchema, config: &GenConfig) -> io::Result<()> {
+    let ir = Schema::new(&schema).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let meta = Metadata::new(&ir);
+    write_layer(f, &schema, config)?;
+
+    write_structs(f, &ir, config, &meta)?;
+    write_enums(f, &ir, config, &meta)?;
+    if config.emit_functions() {
+        write_functions(f, &ir, config, &meta)?;
+    }
 
     writeln!(f)?;
     f.flush()
 }
+
+/// Like [`generate_code`], but with every namespace in a file of its own,
+/// like `types/messages.rs`, next to the `mod.rs` of its module, which also
+/// holds the declarations without a namespace. `dir/mod.rs` is the root,
+/// which includes the rest by relative path.
+pub fn generate_files(dir: &Path, schema: TLSchema, config: &GenConfig) -> io::Result<()> {
+    let ir = Schema::new(&schema).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let meta = Metadata::new(&ir);
+
+    fs::create_dir_all(dir)?;
+    let mut root = BufWriter::new(File::create(dir.join("mod.rs"))?);
+    write_layer(&mut root, &schema, config)?;
+
+    let files = ModuleFiles {
+        dir,
+        config,
+        metadata: &meta,
+    };
+    files.write(&mut root, "types", &meta.types_ns, |f, constrs, indent| {
+        write_struct_items(f, &ir, config, constrs, indent)
+    })?;
+    files.write(
+        &mut root,
+        "enums",
+        &meta.types_group_ns,
+        |f, types, indent| write_enum_items(f, &ir, config, types, indent),
+    )?;
+    if config.emit_functions() {
+        files.write(
+            &mut root,
+            "functions",
+            &meta.funcs_ns,
+            |f, funcs, indent| write_function_items(f, &ir, config, funcs, indent),
+        )?;
+    }
+
+    root.flush()
+}
+
+/// Writes the `LAYER` constant, failing if the schema has no layer and
+/// `config` requires one.
+fn write_layer<W: Write>(f: &mut W, schema: &TLSchema, config: &GenConfig) -> io::Result<()> {
+    match schema.layer {
+        Some(layer) => writeln!(f, "pub const LAYER: u32 = {};", layer),
+        None if config.requires_layer() => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the schema has no `// LAYER N` comment",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Writes module `name`, with a submodule for each namespace, using
+/// `write_items` for the declarations in them.
+pub(crate) fn write_module<W: Write, T>(
+    f: &mut W,
+    config: &GenConfig,
+    metadata: &Metadata,
+    name: &str,
+    namespaces: &BTreeMap<Option<&str>, Vec<T>>,
+    write_items: impl FnMut(&mut W, &[T], &str) -> io::Result<()>,
+) -> io::Result<()> {
+    writeln!(f, "{ALLOW}")?;
+    writeln!(f, "pub mod {name} {{")?;
+    let mut sink = InlineNamespaces(f);
+    write_namespaces(&mut sink, config, metadata, name, namespaces, write_items)?;
+    writeln!(f, "}}")
+}
+
+/// Where [`write_namespaces`] puts the declarations of a module.
+trait NamespaceSink {
+    type Writer: Write;
+
+    /// The module, where namespace modules are declared.
+    fn module(&mut self) -> &mut Self::Writer;
+
+    /// The indentation of what goes in the module.
+    fn indent(&self) -> &'static str;
+
+    /// Declares the module of namespace `ns` and writes its declarations.
+    fn namespace<T>(
+        &mut self,
+        ns: &str,
+        items: &[T],
+        write_items: &mut impl FnMut(&mut Self::Writer, &[T], &str) -> io::Result<()>,
+    ) -> io::Result<()>;
+}
+
+/// Writes the namespaces of module `name` that `config` includes, each
+/// behind its feature gate.
+fn write_namespaces<S: NamespaceSink, T>(
+    sink: &mut S,
+    config: &GenConfig,
+    metadata: &Metadata,
+    name: &str,
+    namespaces: &BTreeMap<Option<&str>, Vec<T>>,
+    mut write_items: impl FnMut(&mut S::Writer, &[T], &str) -> io::Result<()>,
+) -> io::Result<()> {
+    for (ns, items) in namespaces {
+        if !config.includes_namespace(*ns) {
+            continue;
+        }
+        let indent = sink.indent();
+        match ns {
+            None => write_items(sink.module(), items, indent)?,
+            Some(ns) => {
+                write_gate(sink.module(), config, metadata, name, ns, indent)?;
+                sink.namespace(ns, items, &mut write_items)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Namespaces as nested modules, in the same output as their parent.
+struct InlineNamespaces<'w, W>(&'w mut W);
+
+impl<W: Write> NamespaceSink for InlineNamespaces<'_, W> {
+    type Writer = W;
+
+    fn module(&mut self) -> &mut W {
+        self.0
+    }
+
+    fn indent(&self) -> &'static str {
+        "    "
+    }
+
+    fn namespace<T>(
+        &mut self,
+        ns: &str,
+        items: &[T],
+        write_items: &mut impl FnMut(&mut W, &[T], &str) -> io::Result<()>,
+    ) -> io::Result<()> {
+        writeln!(self.0, "    pub mod {ns} {{")?;
+        write_items(self.0, items, "        ")?;
+        writeln!(self.0, "    }}")
+    }
+}
+
+/// Namespaces in files of their own, in `dir`, included by the `mod.rs` of
+/// their parent.
+struct FileNamespaces<'d> {
+    dir: &'d Path,
+    module: BufWriter<File>,
+}
+
+impl NamespaceSink for FileNamespaces<'_> {
+    type Writer = BufWriter<File>;
+
+    fn module(&mut self) -> &mut BufWriter<File> {
+        &mut self.module
+    }
+
+    fn indent(&self) -> &'static str {
+        ""
+    }
+
+    fn namespace<T>(
+        &mut self,
+        ns: &str,
+        items: &[T],
+        write_items: &mut impl FnMut(&mut BufWriter<File>, &[T], &str) -> io::Result<()>,
+    ) -> io::Result<()> {
+        writeln!(self.module, "pub mod {ns} {{ include!(\"{ns}.rs\"); }}")?;
+        let mut file = BufWriter::new(File::create(self.dir.join(format!("{ns}.rs")))?);
+        write_items(&mut file, items, "")?;
+        file.flush()
+    }
+}
+
+/// Where [`generate_files`] puts each module.
+struct ModuleFiles<'d, 'm> {
+    dir: &'d Path,
+    config: &'d GenConfig,
+    metadata: &'d Metadata<'m>,
+}
+
+impl ModuleFiles<'_, '_> {
+    /// Like [`write_module`], into `name/mod.rs` and the files of its
+    /// namespaces, and declares the module in `root`.
+    fn write<T>(
+        &self,
+        root: &mut impl Write,
+        name: &str,
+        namespaces: &BTreeMap<Option<&str>, Vec<T>>,
+        write_items: impl FnMut(&mut BufWriter<File>, &[T], &str) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let dir = self.dir.join(name);
+        fs::create_dir_all(&dir)?;
+        writeln!(root, "{ALLOW}")?;
+        writeln!(root, "pub mod {name} {{ include!(\"{name}/mod.rs\"); }}")?;
+
+        let mut sink = FileNamespaces {
+            dir: &dir,
+            module: BufWriter::new(File::create(dir.join("mod.rs"))?),
+        };
+        write_namespaces(
+            &mut sink,
+            self.config,
+            self.metadata,
+            name,
+            namespaces,
+            write_items,
+        )?;
+        sink.module.flush()
+    }
+}
+
+/// Writes the `cfg` of the feature of namespace `ns` in `module`, if it has
+/// one.
+fn write_gate<W: Write>(
+    f: &mut W,
+    config: &GenConfig,
+    metadata: &Metadata,
+    module: &str,
+    ns: &str,
+    indent: &str,
+) -> io::Result<()> {
+    if config.emit_feature_gates() {
+        if let Some(feature) = metadata.features.gate(module, ns) {
+            writeln!(f, "{indent}#[cfg(feature = \"{feature}\")]")?;
+        }
+    }
+    Ok(())
+}
+
+/// The `[features]` table for a crate built with
+/// [`GenConfig::feature_gates`].
+pub fn cargo_features(schema: &TLSchema) -> io::Result<String> {
+    let ir = Schema::new(schema).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Metadata::new(&ir).features.cargo_table())
+}
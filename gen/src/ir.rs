@@ -0,0 +1,519 @@
+//! Schema with every type reference resolved, as consumed by the generator.
+//!
+//! Building it checks that each term names something the schema declares, so
+//! code generation can rely on the types being there.
+
+use parser::types::{Arg, ArgCond, CombinatorDecl, IdentNs, ResType, TLSchema, Term};
+use std::{collections::HashMap, fmt};
+
+/// Builtin types, which have hand-written Rust counterparts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    Int,
+    Long,
+    Double,
+    String,
+    Bytes,
+    Int128,
+    Int256,
+}
+
+impl Builtin {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "int" => Self::Int,
+            "long" => Self::Long,
+            "double" => Self::Double,
+            "string" => Self::String,
+            "bytes" => Self::Bytes,
+            "int128" => Self::Int128,
+            "int256" => Self::Int256,
+            _ => return None,
+        })
+    }
+}
+
+/// What a term in the schema refers to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type<'a> {
+    Builtin(Builtin),
+    /// A `#` field.
+    Nat,
+    /// A `#` field holding the bits of conditional fields.
+    Flags,
+    /// A `flags.N?true` field, set or unset with no data.
+    FlagBool,
+    /// A bare constructor, by index into [`Schema::constructors`].
+    Bare(usize),
+    /// A boxed type, by index into [`Schema::types`].
+    Boxed(usize),
+    /// `Vector<T>`.
+    Vector(Box<Type<'a>>),
+    /// `vector<T>`, without the constructor ID.
+    BareVector(Box<Type<'a>>),
+    /// A type parameter, like `X` in `{X:Type} query:!X = X`.
+    Generic(&'a str),
+}
+
+/// The bit of a flags field a conditional field depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Flag<'a> {
+    pub field: &'a str,
+    pub bit: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field<'a> {
+    pub name: &'a str,
+    pub ty: Type<'a>,
+    pub flag: Option<Flag<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constructor<'a> {
+    pub decl: &'a CombinatorDecl<'a>,
+    pub id: u32,
+    pub fields: Vec<Field<'a>>,
+    /// The boxed type it builds, by index into [`Schema::types`].
+    pub ty: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function<'a> {
+    pub decl: &'a CombinatorDecl<'a>,
+    pub id: u32,
+    pub fields: Vec<Field<'a>>,
+    pub ret: Type<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoxedType<'a> {
+    pub name: &'a IdentNs<'a>,
+    pub res: &'a ResType<'a>,
+    /// Indices into [`Schema::constructors`].
+    pub constructors: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveError {
+    /// A name that is neither declared nor a type parameter.
+    UnknownType { decl: String, name: String },
+    /// An argument or term the generator can't represent.
+    Unsupported { decl: String, arg: String },
+    /// Two constructors or two functions sharing an ID, which can't be told
+    /// apart on the wire.
+    DuplicateId {
+        id: u32,
+        first: String,
+        second: String,
+    },
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownType { decl, name } => write!(f, "{}: unknown type `{}`", decl, name),
+            Self::Unsupported { decl, arg } => {
+                write!(f, "{}: unsupported argument `{}`", decl, arg)
+            }
+            Self::DuplicateId { id, first, second } => write!(
+                f,
+                "{}: constructor id #{:08x} is already used by {}",
+                second, id, first
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// Resolved schema. Constructors standing for builtins, like `vector` or
+/// `int128`, become [`Type::Builtin`] and [`Type::Vector`] instead of being
+/// listed, and so do their boxed types.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema<'a> {
+    pub types: Vec<BoxedType<'a>>,
+    pub constructors: Vec<Constructor<'a>>,
+    pub functions: Vec<Function<'a>>,
+}
+
+impl<'a> Schema<'a> {
+    /// Resolves `schema`. IDs missing from the source are computed.
+    pub fn new(schema: &'a TLSchema<'a>) -> Result<Self, ResolveError> {
+        let mut ir = Self::default();
+        let mut names = Names::default();
+
+        for decl in &schema.builtin {
+            if let Some(builtin) = Builtin::from_name(&decl.identns.name) {
+                names.builtins.insert(decl.identns.to_string(), builtin);
+                names.builtins.insert(decl.res.to_string(), builtin);
+            }
+        }
+
+        let mut ids = HashMap::new();
+        for decl in &schema.constrs {
+            let name = decl.identns.to_string();
+            let res = decl.res.identns();
+            if let Some(builtin) = Builtin::from_name(&name) {
+                names.builtins.insert(name, builtin);
+                names.builtins.insert(res.to_string(), builtin);
+                continue;
+            }
+            if name == "vector" {
+                continue;
+            }
+            if let Some(first) = ids.insert(decl.get_id(), decl) {
+                return Err(duplicate_id(first, decl));
+            }
+
+            let ty = *names.boxed.entry(res.to_string()).or_insert_with(|| {
+                ir.types.push(BoxedType {
+                    name: res,
+                    res: &decl.res,
+                    constructors: vec![],
+                });
+                ir.types.len() - 1
+            });
+            ir.types[ty].constructors.push(ir.constructors.len());
+            names.bare.insert(name, ir.constructors.len());
+            ir.constructors.push(Constructor {
+                decl,
+                id: decl.get_id(),
+                fields: vec![],
+                ty,
+            });
+        }
+
+        names.only_constructor = ir
+            .types
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| match t.constructors.as_slice() {
+                [c] => Some((i, *c)),
+                _ => None,
+            })
+            .collect();
+        for constr in &mut ir.constructors {
+            constr.fields = names.fields(constr.decl)?;
+        }
+
+        let mut ids = HashMap::new();
+        for decl in &schema.funcs {
+            if let Some(first) = ids.insert(decl.get_id(), decl) {
+                return Err(duplicate_id(first, decl));
+            }
+            let vars = type_vars(decl);
+            let ret = match &decl.res {
+                ResType::Normal(r) if r.terms.is_empty() => names.ident(&r.identns, &vars, decl)?,
+                ResType::Ang(r) if r.terms.is_empty() => {
+                    names.vector(&r.identns, &r.term, &vars, decl, &decl.res)?
+                }
+                res => return Err(unsupported(decl, res)),
+            };
+
+            ir.functions.push(Function {
+                decl,
+                id: decl.get_id(),
+                fields: names.fields(decl)?,
+                ret,
+            });
+        }
+
+        Ok(ir)
+    }
+
+    /// The boxed type of a constructor.
+    pub fn type_of(&self, constr: &Constructor) -> &BoxedType<'a> {
+        &self.types[constr.ty]
+    }
+
+    /// The constructors of a boxed type.
+    pub fn constructors_of<'s>(
+        &'s self,
+        ty: &'s BoxedType,
+    ) -> impl Iterator<Item = &'s Constructor<'a>> + 's {
+        ty.constructors.iter().map(|&i| &self.constructors[i])
+    }
+}
+
+fn duplicate_id(first: &CombinatorDecl, second: &CombinatorDecl) -> ResolveError {
+    ResolveError::DuplicateId {
+        id: second.get_id(),
+        first: first.identns.to_string(),
+        second: second.identns.to_string(),
+    }
+}
+
+fn unsupported(decl: &CombinatorDecl, what: impl fmt::Display) -> ResolveError {
+    ResolveError::Unsupported {
+        decl: decl.identns.to_string(),
+        arg: what.to_string(),
+    }
+}
+
+/// Names bound by `{X:Type}` and similar optional arguments.
+fn type_vars<'a>(decl: &'a CombinatorDecl) -> Vec<&'a str> {
+    decl.opt_args
+        .iter()
+        .flat_map(|a| std::iter::once(&a.ident).chain(&a.idents))
+        .map(|i| i.as_ref())
+        .collect()
+}
+
+/// Lookup tables from schema names to what they resolve to.
+#[derive(Default)]
+struct Names {
+    builtins: HashMap<String, Builtin>,
+    bare: HashMap<String, usize>,
+    boxed: HashMap<String, usize>,
+    /// Boxed types with a single constructor, which `%T` can refer to.
+    only_constructor: HashMap<usize, usize>,
+}
+
+impl Names {
+    fn fields<'a>(&self, decl: &'a CombinatorDecl) -> Result<Vec<Field<'a>>, ResolveError> {
+        let vars = type_vars(decl);
+        let flags = decl
+            .args
+            .iter()
+            .filter_map(|a| match a {
+                Arg::Cond(ArgCond {
+                    cond: Some(cond), ..
+                }) => Some(cond.ident.as_ref()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut words = vec![];
+        decl.args
+            .iter()
+            .map(|arg| {
+                let a = match arg {
+                    Arg::Cond(a) => a,
+                    arg => return Err(unsupported(decl, arg)),
+                };
+                // a condition must test one bit of an earlier flags word
+                let flag = match &a.cond {
+                    Some(c) => match c.index {
+                        Some(bit) if bit < 32 && words.contains(&c.ident.as_ref()) => Some(Flag {
+                            field: c.ident.as_ref(),
+                            bit,
+                        }),
+                        _ => return Err(unsupported(decl, arg)),
+                    },
+                    None => None,
+                };
+                let ty = match &a.term {
+                    Term::Nat(_) if flags.contains(&a.ident.as_ref()) => {
+                        words.push(a.ident.as_ref());
+                        Type::Flags
+                    }
+                    Term::IdentNs(i)
+                        if flag.is_some() && i.namespace.is_none() && i.name == "true" =>
+                    {
+                        Type::FlagBool
+                    }
+                    term => self.term(term, &vars, decl)?,
+                };
+
+                Ok(Field {
+                    name: a.ident.as_ref(),
+                    ty,
+                    flag,
+                })
+            })
+            .collect()
+    }
+
+    fn term<'a>(
+        &self,
+        term: &Term<'a>,
+        vars: &[&'a str],
+        decl: &CombinatorDecl,
+    ) -> Result<Type<'a>, ResolveError> {
+        match term {
+            Term::Nat(_) => Ok(Type::Nat),
+            Term::IdentNs(i) => self.ident(i, vars, decl),
+            Term::Ang(a) if a.terms.is_empty() => {
+                self.vector(&a.identns, &a.term, vars, decl, term)
+            }
+            // `%T` is the only constructor of `T`, without its ID
+            Term::Percent(t) => match self.term(t, vars, decl)? {
+                Type::Boxed(ty) => match self.only_constructor.get(&ty) {
+                    Some(c) => Ok(Type::Bare(*c)),
+                    None => Err(unsupported(decl, term)),
+                },
+                ty => Ok(ty),
+            },
+            _ => Err(unsupported(decl, term)),
+        }
+    }
+
+    /// `Vector<T>` or `vector<T>`, the only generic types in the schemas.
+    fn vector<'a>(
+        &self,
+        ident: &IdentNs,
+        term: &Term<'a>,
+        vars: &[&'a str],
+        decl: &CombinatorDecl,
+        whole: &dyn fmt::Display,
+    ) -> Result<Type<'a>, ResolveError> {
+        let inner = Box::new(self.term(term, vars, decl)?);
+        match (&ident.namespace, ident.name.as_ref()) {
+            (None, "Vector") => Ok(Type::Vector(inner)),
+            (None, "vector") => Ok(Type::BareVector(inner)),
+            _ => Err(unsupported(decl, whole)),
+        }
+    }
+
+    fn ident<'a>(
+        &self,
+        ident: &IdentNs<'a>,
+        vars: &[&'a str],
+        decl: &CombinatorDecl,
+    ) -> Result<Type<'a>, ResolveError> {
+        if ident.namespace.is_none() {
+            if let Some(var) = vars.iter().find(|v| **v == ident.name) {
+                return Ok(Type::Generic(var));
+            }
+        }
+
+        let name = ident.to_string();
+        if let Some(builtin) = self.builtins.get(&name) {
+            Ok(Type::Builtin(*builtin))
+        } else if let Some(builtin) = Builtin::from_name(&name) {
+            Ok(Type::Builtin(builtin))
+        } else if let Some(i) = self.bare.get(&name) {
+            Ok(Type::Bare(*i))
+        } else if let Some(i) = self.boxed.get(&name) {
+            Ok(Type::Boxed(*i))
+        } else {
+            Err(ResolveError::UnknownType {
+                decl: decl.identns.to_string(),
+                name,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Builtin, Flag, ResolveError, Schema, Type};
+    use parser::combinators::schema;
+
+    #[test]
+    fn test_resolve_types() {
+        let schema = schema(
+            "
+            int ? = Int;
+            long ? = Long;
+            vector#1cb5c415 {t:Type} # [ t ] = Vector t;
+            int128 4*[ int ] = Int128;
+            true#3fedd339 = True;
+            future_salt#949d9dc valid_since:int salt:long = FutureSalt;
+            future_salts#ae500895 nonce:int128 salts:vector<future_salt> = FutureSalts;
+            user#1 flags:# self:flags.0?true friends:flags.3?Vector<User> = User;
+            ---functions---
+            invokeWithLayer#da9b0d0d {X:Type} layer:int query:!X = X;
+            getUsers#2 ids:Vector<long> = Vector<User>;
+        ",
+        )
+        .unwrap();
+        let ir = Schema::new(&schema).unwrap();
+
+        let names = ir
+            .constructors
+            .iter()
+            .map(|c| c.decl.identns.name.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["true", "future_salt", "future_salts", "user"]);
+
+        let salts = &ir.constructors[2];
+        assert_eq!(salts.fields[0].ty, Type::Builtin(Builtin::Int128));
+        assert_eq!(
+            salts.fields[1].ty,
+            Type::BareVector(Box::new(Type::Bare(1)))
+        );
+        assert_eq!(ir.type_of(salts).name.name, "FutureSalts");
+
+        let user = &ir.constructors[3];
+        let user_ty = user.ty;
+        assert_eq!(
+            user.fields
+                .iter()
+                .map(|f| (f.ty.clone(), f.flag))
+                .collect::<Vec<_>>(),
+            vec![
+                (Type::Flags, None),
+                (
+                    Type::FlagBool,
+                    Some(Flag {
+                        field: "flags",
+                        bit: 0
+                    })
+                ),
+                (
+                    Type::Vector(Box::new(Type::Boxed(user_ty))),
+                    Some(Flag {
+                        field: "flags",
+                        bit: 3
+                    })
+                ),
+            ]
+        );
+        assert_eq!(
+            ir.constructors_of(&ir.types[user_ty])
+                .map(|c| c.id)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+
+        let invoke = &ir.functions[0];
+        assert_eq!(invoke.ret, Type::Generic("X"));
+        assert_eq!(invoke.fields[1].ty, Type::Generic("X"));
+        assert_eq!(
+            ir.functions[1].ret,
+            Type::Vector(Box::new(Type::Boxed(user_ty)))
+        );
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let unknown = schema("a#1 x:Foo = A;").unwrap();
+        assert_eq!(
+            Schema::new(&unknown),
+            Err(ResolveError::UnknownType {
+                decl: "a".to_string(),
+                name: "Foo".to_string()
+            })
+        );
+
+        let unsupported = schema("int ? = Int; a#1 n:# 2*[ int ] = A;").unwrap();
+        assert_eq!(
+            Schema::new(&unsupported).unwrap_err().to_string(),
+            "a: unsupported argument `2*[ int ]`"
+        );
+
+        let late_flags = schema("int ? = Int; a#1 x:f.0?int f:# = A;").unwrap();
+        assert_eq!(
+            Schema::new(&late_flags).unwrap_err().to_string(),
+            "a: unsupported argument `x:f.0?int`"
+        );
+
+        let duplicate = schema("a#1 = A; b#1 = B;").unwrap();
+        assert_eq!(
+            Schema::new(&duplicate),
+            Err(ResolveError::DuplicateId {
+                id: 1,
+                first: "a".to_string(),
+                second: "b".to_string()
+            })
+        );
+
+        let duplicate = schema("a#1 = A; ---functions--- f#2 = A; g#2 = A;").unwrap();
+        assert_eq!(
+            Schema::new(&duplicate).unwrap_err().to_string(),
+            "g: constructor id #00000002 is already used by f"
+        );
+    }
+}
@@ -1,12 +1,19 @@
+pub mod config;
 pub mod enums;
+pub mod features;
 pub mod formatter;
+pub mod functions;
 pub mod generate;
+pub mod ir;
 pub mod metadata;
 pub mod structs;
 
 #[cfg(test)]
 mod tests {
-    use crate::generate::generate_code;
+    use crate::{
+        config::GenConfig,
+        generate::{cargo_features, generate_code, generate_files},
+    };
     use parser::{combinators::schema, types::TLSchema};
     use std::fs::File;
     use std::io::Read;
@@ -17,12 +24,12 @@ mod tests {
         let mut schema_text = String::new();
         fs.read_to_string(&mut schema_text).unwrap();
 
-        let mut schema = schema(&schema_text).unwrap().1;
+        let mut schema = schema(&schema_text).unwrap();
         schema.calculate_ids();
 
         let mut output = std::io::sink();
 
-        generate_code(&mut output, schema, 142).unwrap();
+        generate_code(&mut output, schema, &GenConfig::default()).unwrap();
     }
 
     #[test]
@@ -31,35 +38,251 @@ mod tests {
         let mut schema_text = String::new();
         fs.read_to_string(&mut schema_text).unwrap();
 
-        let mut schema = schema(&schema_text).unwrap().1;
+        let mut schema = schema(&schema_text).unwrap();
         schema.calculate_ids();
 
         let mut output = std::io::sink();
-        generate_code(&mut output, schema, 142).unwrap();
+        generate_code(&mut output, schema, &GenConfig::default()).unwrap();
     }
 
     #[test]
     fn test_generate_all_scheme() {
-        let mut api_fs = File::open("../data/schema/api.tl").unwrap();
-        let mut mtproto_fs = File::open("../data/schema/mtproto.tl").unwrap();
+        let mut schema =
+            TLSchema::from_paths(["../data/schema/api.tl", "../data/schema/mtproto.tl"]).unwrap();
+        schema.calculate_ids();
 
-        let mut api_text = String::new();
-        let mut mtproto_text = String::new();
+        let mut output = vec![];
+        generate_code(&mut output, schema, &GenConfig::default()).unwrap();
+        let output = String::from_utf8(output).unwrap();
 
-        mtproto_fs.read_to_string(&mut mtproto_text).unwrap();
-        api_fs.read_to_string(&mut api_text).unwrap();
+        assert!(output.starts_with("pub const LAYER: u32 = 143;\n"));
+    }
 
-        let api_schema = schema(&api_text).unwrap().1;
-        let mtproto_schema = schema(&mtproto_text).unwrap().1;
-        
-        let mut schema = TLSchema::default();
-        schema.extend(api_schema);
-        schema.extend(mtproto_schema);
-        schema.calculate_ids();
+    #[test]
+    fn test_generate_deterministic() {
+        let generate = || {
+            let mut schema =
+                TLSchema::from_paths(["../data/schema/api.tl", "../data/schema/mtproto.tl"])
+                    .unwrap();
+            schema.calculate_ids();
 
-        
+            let mut output = vec![];
+            generate_code(&mut output, schema, &GenConfig::default()).unwrap();
+            output
+        };
 
-        let mut output = std::io::sink();
-        generate_code(&mut output, schema, 142).unwrap();
+        assert!(generate() == generate());
+    }
+
+    #[test]
+    fn test_generate_docs() {
+        let text = "\
+//@description A pair of numbers @first The first number
+pair#1 first:int second:int = Pair;
+";
+        let schema = schema(text).unwrap();
+
+        let mut output = vec![];
+        generate_code(&mut output, schema, &GenConfig::default()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("/// A pair of numbers\n    pub struct Pair {"));
+        assert!(output.contains("/// The first number\n        pub first: i32,"));
+        assert!(output.contains("\n        pub second: i32,"));
+    }
+
+    #[test]
+    fn test_generate_enum_des() {
+        let text = "\
+leaf#1 value:int = Tree;
+node#2 left:Tree right:Tree = Tree;
+";
+        let schema = schema(text).unwrap();
+
+        let mut output = vec![];
+        generate_code(&mut output, schema, &GenConfig::default()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("\n        Node(Box<crate::types::Node>),"));
+        assert!(output.contains(
+            "0x00000002 => Self::Node(<Box<crate::types::Node> as crate::Deserializable>::deserialize(buf)?),"
+        ));
+        assert!(output
+            .contains("_ => return Err(crate::Err::UnexpectedConstructor { id, ty: \"Tree\" }),"));
+    }
+
+    #[test]
+    fn test_generate_functions() {
+        let text = "\
+user#1 id:int = User;
+---functions---
+users.getUsers#2 id:Vector<int> = Vector<User>;
+invokeWithLayer#da9b0d0d {X:Type} layer:int query:!X = X;
+";
+        let schema = schema(text).unwrap();
+
+        let mut output = vec![];
+        generate_code(&mut output, schema, &GenConfig::default()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("pub mod users {\n        #[derive(Debug, Clone, PartialEq)]\n        pub struct GetUsers {"));
+        assert!(output.contains(
+            "impl crate::Request for GetUsers { type Response = Vec<crate::enums::User>; }"
+        ));
+        assert!(output.contains("pub struct InvokeWithLayer<X: crate::Request> {"));
+        assert!(output.contains("\n        pub query: X,"));
+        assert!(output.contains(
+            "impl<X: crate::Request> crate::Request for InvokeWithLayer<X> { type Response = X::Response; }"
+        ));
+    }
+
+    #[test]
+    fn test_generate_config() {
+        let text = "\
+//@description A user
+user#1 id:int128 = User;
+auth.authorization#2 user:User = auth.Authorization;
+help.config#3 = help.Config;
+---functions---
+auth.signIn#4 id:int = auth.Authorization;
+";
+        let schema = schema(text).unwrap();
+        let config = GenConfig::new()
+            .root("crate::tl")
+            .derive("Eq")
+            .derive("Eq")
+            .namespaces(["auth"])
+            .docs(false)
+            .deserialize(false);
+
+        let mut output = vec![];
+        generate_code(&mut output, schema, &config).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains(
+            "#[derive(Debug, Clone, PartialEq, Eq)]\n    pub struct User {\n        pub id: crate::tl::Int128,"
+        ));
+        assert!(output.contains("pub user: crate::tl::enums::User,"));
+        assert!(output.contains("impl crate::tl::Request for SignIn"));
+        assert!(!output.contains("crate::Serializable"));
+        assert!(!output.contains("/// A user"));
+        assert!(!output.contains("Deserializable"));
+        assert!(!output.contains("mod help"));
+
+        let schema = self::schema(text).unwrap();
+        let mut output = vec![];
+        generate_code(&mut output, schema, &GenConfig::new().functions(false)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("mod help"));
+        assert!(!output.contains("pub mod functions"));
+
+        let schema = self::schema(text).unwrap();
+        let err = generate_code(&mut vec![], schema, &GenConfig::new().require_layer(true));
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "the schema has no `// LAYER N` comment"
+        );
+    }
+
+    #[test]
+    fn test_generate_feature_gates() {
+        let text = "\
+user#1 id:int = User;
+chat#2 full:users.UserFull = Chat;
+auth.authorization#3 user:User config:help.Config = auth.Authorization;
+account.password#4 = account.Password;
+help.config#5 auth:auth.Authorization = help.Config;
+users.userFull#6 user:User = users.UserFull;
+---functions---
+account.getPassword#7 = account.Password;
+users.getFullUser#8 id:int = users.UserFull;
+";
+        let schema = schema(text).unwrap();
+        assert_eq!(
+            cargo_features(&schema).unwrap(),
+            "\
+[features]
+# The types of these namespaces are always compiled, because declarations
+# without a namespace refer to them: users.
+default = [\"all\"]
+all = [\"account\", \"auth\", \"help\", \"functions-account\", \"functions-users\"]
+account = []
+# `auth` and `help` refer to each other, so enabling either enables both.
+auth = [\"help\"]
+help = [\"auth\"]
+functions-account = [\"account\"]
+functions-users = []
+"
+        );
+
+        let mut output = vec![];
+        generate_code(&mut output, schema, &GenConfig::new().feature_gates(true)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output
+                .matches("#[cfg(feature = \"account\")]\n    pub mod account {")
+                .count(),
+            2
+        );
+        assert!(output.contains("#[cfg(feature = \"functions-account\")]\n    pub mod account {"));
+        assert!(output.contains("#[cfg(feature = \"functions-users\")]\n    pub mod users {"));
+        assert_eq!(output.matches("#[cfg(feature = \"users\")]").count(), 0);
+        assert_eq!(output.matches("#[cfg(feature = \"auth\")]").count(), 2);
+        assert!(output.contains("\n    pub struct User {"));
+    }
+
+    #[test]
+    fn test_raw_features() {
+        let schema =
+            TLSchema::from_paths(["../data/schema/api.tl", "../data/schema/mtproto.tl"]).unwrap();
+        let features = cargo_features(&schema).unwrap();
+        let manifest = std::fs::read_to_string("../raw/Cargo.toml").unwrap();
+        assert!(
+            manifest.contains(&features),
+            "raw/Cargo.toml should have these features:\n{}",
+            features
+        );
+    }
+
+    #[test]
+    fn test_generate_files() {
+        let text = "\
+// LAYER 7
+
+user#1 id:int = User;
+auth.authorization#2 user:User = auth.Authorization;
+---functions---
+auth.signIn#3 id:int = auth.Authorization;
+";
+        /// Removes the directory even when an assertion fails.
+        struct TempDir(std::path::PathBuf);
+        impl Drop for TempDir {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_dir_all(&self.0);
+            }
+        }
+
+        let dir =
+            TempDir(std::env::temp_dir().join(format!("gen-test-files-{}", std::process::id())));
+        let dir = &dir.0;
+        let config = GenConfig::new().feature_gates(true);
+        generate_files(dir, schema(text).unwrap(), &config).unwrap();
+
+        let read = |path: &str| std::fs::read_to_string(dir.join(path)).unwrap();
+        let root = read("mod.rs");
+        assert!(root.starts_with("pub const LAYER: u32 = 7;\n"));
+        assert!(root.contains("pub mod types { include!(\"types/mod.rs\"); }"));
+        assert!(root.contains("pub mod functions { include!(\"functions/mod.rs\"); }"));
+
+        let types = read("types/mod.rs");
+        assert!(types.starts_with(
+            "#[derive(Debug, Clone, PartialEq)]\npub struct User {\n    pub id: i32,"
+        ));
+        assert!(types
+            .ends_with("#[cfg(feature = \"auth\")]\npub mod auth { include!(\"auth.rs\"); }\n"));
+        assert!(read("types/auth.rs")
+            .contains("\npub struct Authorization {\n    pub user: crate::enums::User,"));
+        assert!(read("enums/auth.rs").contains("\npub enum Authorization {"));
+        assert!(read("functions/auth.rs").contains("\npub struct SignIn {"));
     }
 }
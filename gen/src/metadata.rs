@@ -1,38 +1,48 @@
-use parser::types::{CombinatorDecl, ResType, TLSchema};
-use std::collections::{HashMap, HashSet};
+use crate::{
+    features::Features,
+    ir::{BoxedType, Constructor, Function, Schema},
+};
+use std::collections::BTreeMap;
 
+/// Declarations grouped by namespace. Namespaces are ordered by name, with
+/// the root one first, and the declarations in them keep schema order, so
+/// the generated code is the same on every build.
 #[derive(Debug, Default)]
 pub struct Metadata<'a> {
-    pub types_ns: HashMap<Option<&'a str>, HashSet<&'a CombinatorDecl<'a>>>,
-    pub funcs_ns: HashMap<Option<&'a str>, HashSet<&'a CombinatorDecl<'a>>>,
+    pub types_ns: BTreeMap<Option<&'a str>, Vec<&'a Constructor<'a>>>,
+    pub funcs_ns: BTreeMap<Option<&'a str>, Vec<&'a Function<'a>>>,
 
-    pub types_group_ns:
-        HashMap<Option<&'a str>, HashMap<ResType<'a>, HashSet<&'a CombinatorDecl<'a>>>>,
+    pub types_group_ns: BTreeMap<Option<&'a str>, Vec<&'a BoxedType<'a>>>,
+
+    pub features: Features<'a>,
 }
 
 impl<'a> Metadata<'a> {
-    pub fn new(schema: &'a TLSchema) -> Self {
-        let mut meta = Self::default();
+    pub fn new(schema: &'a Schema<'a>) -> Self {
+        let mut meta = Self {
+            features: Features::new(schema),
+            ..Default::default()
+        };
 
-        for ty_decl in &schema.constrs {
+        for constr in &schema.constructors {
             meta.types_ns
-                .entry(ty_decl.identns.namespace)
+                .entry(constr.decl.identns.namespace.as_deref())
                 .or_default()
-                .insert(ty_decl);
+                .push(constr);
         }
 
-        for (ns, decls) in &meta.types_ns {
-            let entry = meta.types_group_ns.entry(*ns).or_default();
-            for decl in decls {
-                entry.entry(decl.res.clone()).or_default().insert(decl);
-            }
+        for ty in &schema.types {
+            meta.types_group_ns
+                .entry(ty.name.namespace.as_deref())
+                .or_default()
+                .push(ty);
         }
 
-        for fn_decl in &schema.funcs {
+        for func in &schema.functions {
             meta.funcs_ns
-                .entry(fn_decl.identns.namespace)
+                .entry(func.decl.identns.namespace.as_deref())
                 .or_default()
-                .insert(fn_decl);
+                .push(func);
         }
 
         meta
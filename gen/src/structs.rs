@@ -1,187 +1,299 @@
 use crate::{
+    config::GenConfig,
     formatter::{builtin_type, escape_builtin_kw, Rustifiable},
+    generate::write_module,
+    ir::{Constructor, Field, Schema, Type},
     metadata::Metadata,
 };
 use itertools::Itertools;
-use parser::types::{Arg, CombinatorDecl, IdentNs, Term};
+use parser::types::CombinatorDecl;
 use std::io::{self, Write};
 
-fn type_from_identns(ins: &IdentNs) -> String {
-    if let Some(bty) = builtin_type(ins.name) {
-        bty.to_string()
-    } else {
-        let module = if ins.is_boxed() { "enums" } else { "types" };
-        format!("crate::{}::{}", module, ins.rust_path())
+pub fn rust_type(schema: &Schema, config: &GenConfig, ty: &Type) -> String {
+    let root = config.root_path();
+    match ty {
+        Type::Builtin(b) => builtin_type(*b, root),
+        Type::Nat | Type::Flags => "u32".to_string(),
+        Type::FlagBool => format!("{root}::types::True"),
+        Type::Bare(i) => format!(
+            "{root}::types::{}",
+            schema.constructors[*i].decl.identns.rust_path()
+        ),
+        Type::Boxed(i) => format!("{root}::enums::{}", schema.types[*i].name.rust_path()),
+        Type::Vector(ty) => format!("Vec<{}>", rust_type(schema, config, ty)),
+        Type::BareVector(ty) => format!("{root}::RawVec<{}>", rust_type(schema, config, ty)),
+        Type::Generic(name) => name.to_string(),
     }
 }
 
-fn type_from_term(term: &Term) -> String {
-    match term {
-        Term::IdentNs(ins) => type_from_identns(ins),
-        Term::Ang(ang) => {
-            let ty = type_from_identns(&ang.identns);
-            let ang_ty = type_from_term(&ang.term);
-            format!("{}<{}>", ty, ang_ty)
-        }
-        Term::Percent(p) => type_from_term(p),
-        Term::Nat => "u32".to_string(),
-        _ => unimplemented!(),
+/// The type of a struct field. A `flags.N?true` field only sets a bit, so
+/// it becomes a `bool`; other conditional fields are `Option`s.
+pub fn get_struct_field_type(schema: &Schema, config: &GenConfig, field: &Field) -> String {
+    match (&field.ty, field.flag) {
+        (Type::FlagBool, _) => "bool".to_string(),
+        (ty, Some(_)) => format!("Option<{}>", rust_type(schema, config, ty)),
+        (ty, None) => rust_type(schema, config, ty),
     }
 }
 
-pub fn get_struct_field_type(arg: &Arg) -> String {
-    match arg {
-        Arg::Cond(a) => {
-            let ty = type_from_term(&a.term);
-            if a.cond.is_some() {
-                format!("Option<{}>", ty)
-            } else {
-                ty
-            }
-        }
-        _ => unimplemented!(),
-    }
+/// The fields whose presence is recorded in the flags word `word`, with
+/// their bit.
+fn flagged_by<'f, 'a>(
+    fields: &'f [Field<'a>],
+    word: &'f Field<'a>,
+) -> impl Iterator<Item = (&'f Field<'a>, u32)> {
+    fields.iter().filter_map(move |f| match f.flag {
+        Some(flag) if flag.field == word.name => Some((f, 1u32 << flag.bit)),
+        _ => None,
+    })
 }
 
-fn get_struct_field_name(arg: &Arg) -> String {
-    match arg {
-        Arg::Cond(a) => escape_builtin_kw(a.ident),
-        _ => unimplemented!(),
+fn collect_type_vars<'a>(ty: &Type<'a>, vars: &mut Vec<&'a str>) {
+    match ty {
+        Type::Generic(name) if !vars.contains(name) => vars.push(name),
+        Type::Vector(ty) | Type::BareVector(ty) => collect_type_vars(ty, vars),
+        _ => (),
     }
 }
 
-fn get_conditional_args<'a>(args: &[Arg<'a>]) -> Vec<&'a str> {
-    let mut list = vec![];
-    for arg in args {
-        match arg {
-            Arg::Cond(c) if c.cond.is_some() => match &c.cond {
-                Some(s) => list.push(s.ident),
-                None => (),
-            },
-            _ => (),
-        }
+/// The type parameters of a struct, one for each type variable in its
+/// fields, as declared and as used, like `<X: crate::Request>` and `<X>`.
+/// Only wrapper functions like `invokeWithLayer` have them.
+pub(crate) fn get_generics(config: &GenConfig, fields: &[Field]) -> (String, String) {
+    let root = config.root_path();
+    let mut vars = vec![];
+    fields
+        .iter()
+        .for_each(|f| collect_type_vars(&f.ty, &mut vars));
+    if vars.is_empty() {
+        return (String::new(), String::new());
     }
 
-    list
+    (
+        format!(
+            "<{}>",
+            vars.iter()
+                .map(|v| format!("{v}: {root}::Request"))
+                .join(", ")
+        ),
+        format!("<{}>", vars.join(", ")),
+    )
 }
 
-fn write_struct<W: Write>(f: &mut W, decl: &CombinatorDecl, indent: &str) -> io::Result<()> {
-    let struct_name = decl.identns.rust_name();
-    let conditionals = get_conditional_args(&decl.args);
+fn get_struct_field_name(field: &Field) -> String {
+    escape_builtin_kw(field.name)
+}
+
+pub(crate) fn write_doc<W: Write>(
+    f: &mut W,
+    config: &GenConfig,
+    doc: Option<String>,
+    indent: &str,
+) -> io::Result<()> {
+    if let Some(doc) = doc.filter(|_| config.emit_docs()) {
+        writeln!(f, "{indent}/// {doc}")?;
+    }
+    Ok(())
+}
 
-    writeln!(f, "{indent}pub struct {struct_name} {{")?;
+/// Writes a struct holding `fields`, except for flags, which are computed
+/// when serializing.
+pub(crate) fn write_struct<W: Write>(
+    f: &mut W,
+    schema: &Schema,
+    config: &GenConfig,
+    decl: &CombinatorDecl,
+    id: u32,
+    fields: &[Field],
+    indent: &str,
+) -> io::Result<()> {
+    let root = config.root_path();
+    let struct_name = decl.identns.rust_name();
+    let (params, args) = get_generics(config, fields);
 
-    for arg in &decl.args {
-        let name = get_struct_field_name(arg);
+    writeln!(f, "{indent}#[derive({})]", config.derives())?;
+    write_doc(f, config, decl.docs.description(), indent)?;
+    writeln!(f, "{indent}pub struct {struct_name}{params} {{")?;
 
-        if !conditionals.iter().any(|c| *c == name) {
-            let ty = get_struct_field_type(arg);
+    for field in fields {
+        if field.ty != Type::Flags {
+            let name = get_struct_field_name(field);
+            let ty = get_struct_field_type(schema, config, field);
+            write_doc(
+                f,
+                config,
+                decl.docs.param(field.name),
+                &format!("{indent}    "),
+            )?;
             writeln!(f, "{indent}    pub {name}: {ty},")?;
         }
     }
     writeln!(f, "{indent}}}")?;
 
-    let id = decl.get_id();
     writeln!(
         f,
-        "{indent}impl crate::Identificable for {struct_name} {{ const ID: u32 = {id}; }}"
+        "{indent}impl{params} {root}::Identificable for {struct_name}{args} {{ const ID: u32 = {id}; }}"
     )?;
     Ok(())
 }
 
-fn write_struct_ser<W: Write>(f: &mut W, decl: &CombinatorDecl, indent: &str) -> io::Result<()> {
-    let struct_name = decl.identns.rust_name();
-    let conditionals = get_conditional_args(&decl.args);
+/// Writes the statements serializing `fields` from `self`.
+pub(crate) fn write_fields_ser<W: Write>(
+    f: &mut W,
+    fields: &[Field],
+    indent: &str,
+) -> io::Result<()> {
+    for field in fields {
+        let name = get_struct_field_name(field);
 
-    writeln!(f, "{indent}impl crate::Serializable for {struct_name} {{")?;
-    writeln!(
-        f,
-        "{indent}    fn serialize(&self, {}buf: crate::serialize::Buf) {{",
-        if decl.args.is_empty() { "_" } else { "" }
-    )?;
-    for arg in &decl.args {
-        let name = get_struct_field_name(arg);
-
-        if !conditionals.iter().any(|c| *c == name) {
-            match arg {
-                Arg::Cond(c) if c.cond.is_some() => {
-                    writeln!(f, "{indent}        if let Some(ref x) = self.{name} {{")?;
-                    writeln!(f, "{indent}            x.serialize(buf);")?;
-                    writeln!(f, "{indent}        }}")?;
-                }
-                _ => writeln!(f, "{indent}        self.{name}.serialize(buf);")?,
-            }
-        } else {
-            let flag_names = decl
-                .args
-                .iter()
-                .filter(|a| match a {
-                    Arg::Cond(c) => matches!(&c.cond, Some(cond) if cond.ident == name),
-                    _ => false,
-                })
-                .map(|a| match a {
-                    Arg::Cond(c) => (escape_builtin_kw(c.ident), c.cond.as_ref().unwrap().index.unwrap_or(0)),
-                    _ => panic!(),
-                });
-            let fill_flag = flag_names
-                .map(|(attr, idx)| {
-                    format!("if self.{attr}.is_some() {{ {} }} else {{ 0 }}", 1 << idx)
+        if field.ty == Type::Flags {
+            // flags words aren't stored, they're computed from the fields
+            let fill_flag = flagged_by(fields, field)
+                .map(|(f, bit)| {
+                    let attr = get_struct_field_name(f);
+                    if f.ty == Type::FlagBool {
+                        format!("if self.{attr} {{ {bit}u32 }} else {{ 0 }}")
+                    } else {
+                        format!("if self.{attr}.is_some() {{ {bit}u32 }} else {{ 0 }}")
+                    }
                 })
                 .join(" | ");
-            // is flag
-            writeln!(f, "{indent}        ({fill_flag}).serialize(buf);")?;
+            if fill_flag.is_empty() {
+                writeln!(f, "{indent}0u32.serialize(buf);")?;
+            } else {
+                writeln!(f, "{indent}({fill_flag}).serialize(buf);")?;
+            }
+        } else if field.ty == Type::FlagBool {
+            // only its bit in the flags is written
+        } else if field.flag.is_some() {
+            writeln!(f, "{indent}if let Some(ref x) = self.{name} {{")?;
+            writeln!(f, "{indent}    x.serialize(buf);")?;
+            writeln!(f, "{indent}}}")?;
+        } else {
+            writeln!(f, "{indent}self.{name}.serialize(buf);")?;
         }
     }
-
-    writeln!(f, "{indent}    }}")?;
-    writeln!(f, "{indent}}}")?;
-
     Ok(())
 }
 
-fn write_struct_des<W: Write>(f: &mut W, decl: &CombinatorDecl, indent: &str) -> io::Result<()> {
-    let struct_name = decl.identns.rust_name();
-    let conditionals = get_conditional_args(&decl.args);
+fn write_struct_ser<W: Write>(
+    f: &mut W,
+    config: &GenConfig,
+    constr: &Constructor,
+    indent: &str,
+) -> io::Result<()> {
+    let root = config.root_path();
+    let struct_name = constr.decl.identns.rust_name();
 
-    writeln!(f, "{indent}impl crate::Deserializable for {struct_name} {{")?;
+    writeln!(f, "{indent}impl {root}::Serializable for {struct_name} {{")?;
     writeln!(
         f,
-        "{indent}    fn deserialize(buf: crate::deserialize::Buf) -> crate::Result<Self> {{"
+        "{indent}    fn serialize(&self, {}buf: {root}::serialize::Buf) {{",
+        if constr.fields.is_empty() { "_" } else { "" }
     )?;
-
+    write_fields_ser(f, &constr.fields, &format!("{indent}        "))?;
     writeln!(f, "{indent}    }}")?;
     writeln!(f, "{indent}}}")?;
+
     Ok(())
 }
 
-pub fn write_structs<W: Write>(f: &mut W, metadata: &Metadata) -> io::Result<()> {
-    writeln!(f, "pub mod types {{")?;
+fn write_struct_des<W: Write>(
+    f: &mut W,
+    schema: &Schema,
+    config: &GenConfig,
+    constr: &Constructor,
+    indent: &str,
+) -> io::Result<()> {
+    let root = config.root_path();
+    let struct_name = constr.decl.identns.rust_name();
 
-    for (ns, decls) in &metadata.types_ns {
-        let indent = if let Some(ns) = ns {
-            writeln!(f, "    pub mod {ns} {{")?;
-            "        "
-        } else {
-            "    "
-        };
+    writeln!(
+        f,
+        "{indent}impl {root}::Deserializable for {struct_name} {{"
+    )?;
+    writeln!(
+        f,
+        "{indent}    fn deserialize({}buf: {root}::deserialize::Buf) -> {root}::Result<Self> {{",
+        if constr.fields.is_empty() { "_" } else { "" }
+    )?;
+    for field in &constr.fields {
+        let name = get_struct_field_name(field);
+        let ty = rust_type(schema, config, &field.ty);
 
-        for decl in decls {
-            match decl.identns.name {
-                "int128" | "int256" | "vector" => continue,
-                _ => (),
+        if let Some(flag) = field.flag {
+            let flags = escape_builtin_kw(flag.field);
+            let bit = 1u32 << flag.bit;
+            if field.ty == Type::FlagBool {
+                writeln!(f, "{indent}        let {name} = {flags} & {bit} != 0;")?;
+            } else {
+                writeln!(
+                    f,
+                    "{indent}        let {name} = if {flags} & {bit} != 0 {{ Some(<{ty} as {root}::Deserializable>::deserialize(buf)?) }} else {{ None }};"
+                )?;
             }
-            writeln!(f, "{indent}#[derive(Debug, Clone, PartialEq)]")?;
-            write_struct(f, decl, indent)?;
-            write_struct_ser(f, decl, indent)?;
-            //write_struct_des(f, &decl, indent)?;
+        } else if field.ty == Type::Flags && flagged_by(&constr.fields, field).next().is_none() {
+            writeln!(
+                f,
+                "{indent}        let _ = <u32 as {root}::Deserializable>::deserialize(buf)?;"
+            )?;
+        } else {
+            writeln!(
+                f,
+                "{indent}        let {name} = <{ty} as {root}::Deserializable>::deserialize(buf)?;"
+            )?;
         }
+    }
+    let fields = constr
+        .fields
+        .iter()
+        .filter(|f| f.ty != Type::Flags)
+        .map(get_struct_field_name)
+        .join(", ");
+    writeln!(f, "{indent}        Ok(Self {{ {fields} }})")?;
+    writeln!(f, "{indent}    }}")?;
+    writeln!(f, "{indent}}}")?;
+    Ok(())
+}
 
-        if ns.is_some() {
-            writeln!(f, "    }}")?;
+/// Writes the structs of the constructors in one namespace.
+pub(crate) fn write_struct_items<W: Write>(
+    f: &mut W,
+    schema: &Schema,
+    config: &GenConfig,
+    constrs: &[&Constructor],
+    indent: &str,
+) -> io::Result<()> {
+    for constr in constrs {
+        write_struct(
+            f,
+            schema,
+            config,
+            constr.decl,
+            constr.id,
+            &constr.fields,
+            indent,
+        )?;
+        write_struct_ser(f, config, constr, indent)?;
+        if config.emit_deserialize() {
+            write_struct_des(f, schema, config, constr, indent)?;
         }
     }
-
-    writeln!(f, "}}")?;
-
     Ok(())
 }
+
+pub fn write_structs<W: Write>(
+    f: &mut W,
+    schema: &Schema,
+    config: &GenConfig,
+    metadata: &Metadata,
+) -> io::Result<()> {
+    write_module(
+        f,
+        config,
+        metadata,
+        "types",
+        &metadata.types_ns,
+        |f, constrs, indent| write_struct_items(f, schema, config, constrs, indent),
+    )
+}
@@ -1,9 +1,13 @@
+use crate::{
+    errors::{Error, PResult},
+    span::Span,
+};
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag},
     character::complete::{alphanumeric1, anychar, char, multispace0},
     combinator::{map_res, opt, recognize, value, verify},
-    error::ParseError,
+    error::{context, ParseError},
     multi::many1_count,
     sequence::{delimited, pair, preceded, terminated},
     AsChar, IResult, InputTakeAtPosition, Parser,
@@ -19,62 +23,97 @@ where
     delimited(multispace0, inner, multispace0)
 }
 
-pub fn comments_inner(input: &str) -> IResult<&str, &str> {
+/// Runs `inner` and returns the span of what it consumed, ignoring
+/// surrounding whitespace.
+pub fn spanned<'a, F, O>(mut inner: F) -> impl FnMut(&'a str) -> PResult<'a, (O, Span)>
+where
+    F: Parser<&'a str, O, Error<'a>>,
+{
+    move |input: &'a str| {
+        let (rest, o) = inner.parse(input)?;
+        let consumed = &input[..input.len() - rest.len()];
+        let start = input.len() - (consumed.len() - consumed.trim_start().len());
+        let end = start - consumed.trim().len();
+
+        Ok((rest, (o, Span::from_remaining(start, end))))
+    }
+}
+
+pub fn comments_inner(input: &str) -> PResult<'_, &str> {
     alt((
-        preceded(tag("//"), is_not("\n\r")),
+        preceded(
+            tag("//"),
+            opt(is_not("\n\r")).map(Option::unwrap_or_default),
+        ),
         delimited(tag("/*"), is_not("*/"), tag("*/")),
     ))(input)
 }
 
-pub fn comments(input: &str) -> IResult<&str, ()> {
+pub fn comments(input: &str) -> PResult<'_, ()> {
     value((), comments_inner)(input)
 }
 
-pub fn var_ident(input: &str) -> IResult<&str, &str> {
-    recognize(many1_count(alt((alphanumeric1, tag("_")))))(input)
+pub fn var_ident(input: &str) -> PResult<'_, &str> {
+    context(
+        "identifier",
+        recognize(many1_count(alt((alphanumeric1, tag("_"))))),
+    )(input)
 }
 
-fn lc_ident(input: &str) -> IResult<&str, &str> {
+fn lc_ident(input: &str) -> PResult<'_, &str> {
     recognize(pair(
         verify(anychar, char::is_ascii_lowercase),
         opt(var_ident),
     ))(input)
 }
 
-fn uc_ident(input: &str) -> IResult<&str, &str> {
+fn uc_ident(input: &str) -> PResult<'_, &str> {
     recognize(pair(
         verify(anychar, char::is_ascii_uppercase),
         opt(var_ident),
     ))(input)
 }
 
-fn lc_ident_ns(input: &str) -> IResult<&str, (Option<&str>, &str)> {
+pub fn lc_ident_ns(input: &str) -> PResult<'_, (Option<&str>, &str)> {
     pair(opt(terminated(lc_ident, char('.'))), lc_ident)(input)
 }
 
-pub fn uc_ident_ns(input: &str) -> IResult<&str, (Option<&str>, &str)> {
-    pair(opt(terminated(lc_ident, char('.'))), uc_ident)(input)
+pub fn uc_ident_ns(input: &str) -> PResult<'_, (Option<&str>, &str)> {
+    context(
+        "type name",
+        pair(opt(terminated(lc_ident, char('.'))), uc_ident),
+    )(input)
 }
 
-pub fn ident_ns(input: &str) -> IResult<&str, (Option<&str>, &str)> {
+pub fn ident_ns(input: &str) -> PResult<'_, (Option<&str>, &str)> {
     pair(opt(terminated(ws(lc_ident), ws(char('.')))), ws(var_ident))(input)
 }
 
-type IdentFull<'a> = ((Option<&'a str>, &'a str), Option<u32>);
+type IdentFull<'a> = (((Option<&'a str>, &'a str), Span), Option<u32>);
 
-pub fn lc_ident_full(input: &str) -> IResult<&str, IdentFull> {
+pub fn lc_ident_full(input: &str) -> PResult<'_, IdentFull<'_>> {
     let from_str_radix_16 = |h| u32::from_str_radix(h, 16);
 
     pair(
-        ws(lc_ident_ns),
+        context(
+            "combinator name",
+            // `_` stands for an anonymous combinator
+            ws(spanned(alt((
+                lc_ident_ns,
+                verify(var_ident, |s: &str| s == "_").map(|s| (None, s)),
+            )))),
+        ),
         opt(preceded(
             ws(char('#')),
-            map_res(
-                ws(recognize(many1_count(verify(
-                    anychar,
-                    char::is_ascii_hexdigit,
-                )))),
-                from_str_radix_16,
+            context(
+                "hexadecimal constructor id",
+                map_res(
+                    ws(recognize(many1_count(verify(
+                        anychar,
+                        char::is_ascii_hexdigit,
+                    )))),
+                    from_str_radix_16,
+                ),
             ),
         )),
     )(input)
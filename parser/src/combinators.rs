@@ -1,77 +1,107 @@
 use crate::{
-    basics::{comments, ident_ns, lc_ident_full, uc_ident_ns, var_ident, ws},
+    basics::{
+        comments_inner, ident_ns, lc_ident_full, lc_ident_ns, spanned, uc_ident_ns, var_ident, ws,
+    },
+    errors::{Diagnostic, Error, PResult, ParsingError},
+    span::LineIndex,
     types::{
         Arg, ArgBrack, ArgCond, ArgPar, ArgSingle, BuiltinDecl, CombinatorDecl, ConditionalDef,
-        Declaration, OptArg, ResType, ResTypeAng, ResTypeNormal, Section, TLSchema, Term, TermAng,
+        Declaration, Docs, FinalDecl, FinalKind, OptArg, PartialAppDecl, ResType, ResTypeAng,
+        ResTypeNormal, Section, TLSchema, Term, TermAng,
     },
 };
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::{char, digit1},
-    combinator::{eof, map_res, opt, value},
+    character::complete::{alpha1, char, digit1, multispace0, multispace1},
+    combinator::{map_res, not, opt, value},
+    error::{context, ParseError},
     multi::{many0, many1},
     sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
-    IResult, Parser,
+    Offset, Parser,
 };
+use std::borrow::Cow;
 
-fn constr_sep(input: &str) -> IResult<&str, Section> {
+fn constr_sep(input: &str) -> PResult<'_, Section> {
     value(
         Section::Types,
         delimited(ws(tag("---")), ws(tag("types")), ws(tag("---"))),
     )(input)
 }
 
-fn func_sep(input: &str) -> IResult<&str, Section> {
+fn func_sep(input: &str) -> PResult<'_, Section> {
     value(
         Section::Function,
         delimited(ws(tag("---")), ws(tag("functions")), ws(tag("---"))),
     )(input)
 }
 
-fn type_term(input: &str) -> IResult<&str, Term> {
+fn type_term(input: &str) -> PResult<'_, Term<'_>> {
     alt((
         // ident_ns <term>
-        pair(
-            ws(ident_ns),
+        spanned(pair(
+            ws(spanned(ident_ns)),
             delimited(
                 ws(char('<')),
                 pair(ws(term), many0(preceded(ws(char(',')), ws(term)))),
                 ws(char('>')),
             ),
-        )
-        .map(|(ident, (term, terms))| {
+        ))
+        .map(|((ident, (term, terms)), span)| {
             TermAng {
                 identns: ident.into(),
                 term: Box::new(term),
                 terms,
+                span,
             }
             .into()
         }),
         // ident_ns
-        ws(ident_ns).map(|ident| Term::IdentNs(ident.into())),
+        ws(spanned(ident_ns)).map(|ident| Term::IdentNs(ident.into())),
         // ( expr )
-        delimited(ws(char('(')), many1(ws(term)), ws(char('('))).map(Term::Par),
+        ws(spanned(delimited(
+            ws(char('(')),
+            many1(ws(term)),
+            char(')'),
+        )))
+        .map(|(terms, span)| Term::Par(terms, span)),
         // nat
-        ws(char('#')).map(|_| Term::Nat),
+        ws(spanned(char('#'))).map(|(_, span)| Term::Nat(span)),
         // %
         pair(ws(char('%')), ws(term)).map(|(_, term)| Term::Percent(Box::new(term))),
     ))(input)
 }
 
-fn nat_term(input: &str) -> IResult<&str, Term> {
-    // digit
-    map_res(ws(digit1), |d: &str| d.parse::<u32>())
-        .map(Term::NatConst)
-        .parse(input)
+fn nat_term(input: &str) -> PResult<'_, Term<'_>> {
+    // digit, not the start of an identifier
+    ws(spanned(map_res(
+        terminated(digit1, not(alt((alpha1, tag("_"))))),
+        |d: &str| d.parse::<u32>(),
+    )))
+    .map(|(n, span)| Term::NatConst(n, span))
+    .parse(input)
 }
 
-fn term(input: &str) -> IResult<&str, Term> {
-    alt((ws(type_term), ws(nat_term)))(input)
+fn term(input: &str) -> PResult<'_, Term<'_>> {
+    context(
+        "type",
+        spanned(pair(
+            alt((ws(nat_term), ws(type_term))),
+            opt(preceded(
+                ws(char('+')),
+                map_res(ws(digit1), |d: &str| d.parse::<u32>()),
+            )),
+        )),
+    )
+    .map(|((term, plus), span)| match plus {
+        Some(n) => Term::Plus(Box::new(term), n, span),
+        None => term,
+    })
+    .parse(input)
 }
 
-fn conditional_def(input: &str) -> IResult<&str, (&str, Option<u32>)> {
-    terminated(
+fn conditional_def(input: &str) -> PResult<'_, ConditionalDef<'_>> {
+    spanned(terminated(
         pair(
             ws(var_ident),
             opt(preceded(
@@ -80,68 +110,78 @@ fn conditional_def(input: &str) -> IResult<&str, (&str, Option<u32>)> {
             )),
         ),
         ws(char('?')),
-    )(input)
+    ))
+    .map(ConditionalDef::from)
+    .parse(input)
 }
 
-fn arg(input: &str) -> IResult<&str, Arg> {
+fn arg(input: &str) -> PResult<'_, Arg<'_>> {
     alt((
         // par
-        delimited(
+        spanned(delimited(
             ws(char('(')),
             separated_pair(
                 pair(ws(var_ident), many0(ws(var_ident))),
                 ws(char(':')),
                 pair(opt(ws(char('!'))), term),
             ),
-            ws(char('(')),
-        )
-        .map(|((ident, idents), (excl, term))| {
+            ws(char(')')),
+        ))
+        .map(|(((ident, idents), (excl, term)), span)| {
             ArgPar {
-                ident,
-                idents,
+                ident: ident.into(),
+                idents: idents.into_iter().map(Cow::from).collect(),
                 term,
                 excl: excl.is_some(),
+                span,
             }
             .into()
         }),
         // brackets
-        tuple((
+        spanned(tuple((
             opt(terminated(ws(var_ident), ws(char(':')))),
-            opt(terminated(
-                map_res(ws(digit1), |d: &str| d.parse::<i32>()),
-                ws(char('*')),
-            )),
+            opt(terminated(ws(term), ws(char('*')))),
             delimited(ws(char('[')), many1(ws(arg)), ws(char(']'))),
-        ))
-        .map(|(ident, mult, args)| ArgBrack { ident, mult, args }.into()),
+        )))
+        .map(|((ident, mult, args), span)| {
+            ArgBrack {
+                ident: ident.map(Cow::from),
+                mult,
+                args,
+                span,
+            }
+            .into()
+        }),
         // conditional
-        separated_pair(
+        spanned(separated_pair(
             ws(var_ident),
             ws(char(':')),
             tuple((opt(ws(conditional_def)), opt(ws(char('!'))), ws(type_term))),
-        )
-        .map(|(ident, (cond, excl, term))| {
+        ))
+        .map(|((ident, (cond, excl, term)), span)| {
             ArgCond {
-                ident,
+                ident: ident.into(),
                 term,
-                cond: cond.map(ConditionalDef::from),
+                cond,
                 excl: excl.is_some(),
+                span,
             }
             .into()
         }),
         // single
-        pair(opt(ws(char('!'))), ws(term)).map(|(excl, term)| {
+        spanned(pair(opt(ws(char('!'))), ws(term))).map(|((excl, term), span)| {
             ArgSingle {
                 term,
                 excl: excl.is_some(),
+                span,
             }
             .into()
         }),
     ))(input)
 }
 
-fn opt_arg(input: &str) -> IResult<&str, OptArg> {
-    delimited(
+fn opt_arg(input: &str) -> PResult<'_, OptArg<'_>> {
+    spanned(delimited(
         ws(char('{')),
         separated_pair(
             pair(ws(var_ident), many0(ws(var_ident))),
@@ -149,153 +189,362 @@ fn opt_arg(input: &str) -> IResult<&str, OptArg> {
             pair(opt(ws(char('!'))), many1(ws(term))),
         ),
         ws(char('}')),
-    )
-    .map(|((ident, idents), (excl, terms))| OptArg {
-        ident,
-        idents,
+    ))
+    .map(|(((ident, idents), (excl, terms)), span)| OptArg {
+        ident: ident.into(),
+        idents: idents.into_iter().map(Cow::from).collect(),
         excl: excl.is_some(),
         terms,
+        span,
     })
     .parse(input)
 }
 
-fn result_type(input: &str) -> IResult<&str, ResType> {
-    alt((
-        pair(
-            ws(uc_ident_ns),
-            delimited(
-                ws(char('<')),
-                pair(ws(term), many0(preceded(ws(char(',')), ws(term)))),
-                ws(char('>')),
+fn result_type(input: &str) -> PResult<'_, ResType<'_>> {
+    context(
+        "result type",
+        alt((
+            spanned(pair(
+                ws(spanned(uc_ident_ns)),
+                delimited(
+                    ws(char('<')),
+                    pair(ws(term), many0(preceded(ws(char(',')), ws(term)))),
+                    ws(char('>')),
+                ),
+            ))
+            .map(|((ident, (term, terms)), span)| {
+                ResTypeAng {
+                    identns: ident.into(),
+                    term,
+                    terms,
+                    span,
+                }
+                .into()
+            }),
+            spanned(pair(ws(spanned(uc_ident_ns)), many0(ws(term)))).map(
+                |((ident, terms), span)| {
+                    ResTypeNormal {
+                        identns: ident.into(),
+                        terms,
+                        span,
+                    }
+                    .into()
+                },
             ),
-        )
-        .map(|(ident, (term, terms))| {
-            ResTypeAng {
-                identns: ident.into(),
-                term,
-                terms,
-            }
-            .into()
-        }),
-        pair(ws(uc_ident_ns), many0(ws(term))).map(|(ident, terms)| {
-            ResTypeNormal {
-                identns: ident.into(),
-                terms,
-            }
-            .into()
-        }),
-    ))(input)
+        )),
+    )(input)
 }
 
-fn combinator_declaration(input: &str) -> IResult<&str, CombinatorDecl> {
-    separated_pair(
+pub(crate) fn combinator_declaration(input: &str) -> PResult<'_, CombinatorDecl<'_>> {
+    spanned(separated_pair(
         tuple((ws(lc_ident_full), many0(ws(opt_arg)), many0(ws(arg)))),
         ws(char('=')),
         terminated(ws(result_type), ws(char(';'))),
+    ))
+    .map(
+        |((((ident, id), opt_args, args), res), span)| CombinatorDecl {
+            identns: ident.into(),
+            id,
+            opt_args,
+            args,
+            res,
+            docs: Docs::default(),
+            span,
+        },
     )
-    .map(|(((ident, id), opt_args, args), res)| CombinatorDecl {
-        identns: ident.into(),
-        id,
-        opt_args,
-        args,
-        res,
-    })
     .parse(input)
 }
 
-fn builtin_declaration(input: &str) -> IResult<&str, BuiltinDecl> {
-    separated_pair(
+fn builtin_declaration(input: &str) -> PResult<'_, BuiltinDecl<'_>> {
+    spanned(separated_pair(
         ws(lc_ident_full),
         pair(ws(char('?')), ws(char('='))),
-        terminated(ws(uc_ident_ns), ws(char(';'))),
-    )
-    .map(|((name_ident, id), res_ident)| BuiltinDecl {
+        terminated(ws(spanned(uc_ident_ns)), ws(char(';'))),
+    ))
+    .map(|(((name_ident, id), res_ident), span)| BuiltinDecl {
         identns: name_ident.into(),
         id,
         res: res_ident.into(),
+        docs: Docs::default(),
+        span,
     })
     .parse(input)
 }
 
-fn tl_program(input: &str) -> IResult<&str, Vec<Declaration>> {
-    terminated(
-        pair(
-            many1(delimited(
-                many0(ws(comments)),
-                alt((
-                    ws(combinator_declaration).map(Declaration::Constr),
-                    ws(builtin_declaration).map(Declaration::Builtin),
-                )),
-                many0(ws(comments)),
-            )),
-            many1(alt((
-                preceded(
-                    ws(func_sep),
-                    many1(delimited(
-                        many0(ws(comments)),
-                        alt((
-                            ws(combinator_declaration).map(Declaration::Fun),
-                            ws(builtin_declaration).map(Declaration::Builtin),
-                        )),
-                        many0(ws(comments)),
-                    )),
-                ),
-                preceded(
-                    ws(constr_sep),
-                    many1(delimited(
-                        many0(ws(comments)),
-                        alt((
-                            ws(combinator_declaration).map(Declaration::Constr),
-                            ws(builtin_declaration).map(Declaration::Builtin),
-                        )),
-                        many0(ws(comments)),
-                    )),
+fn final_declaration(input: &str) -> PResult<'_, FinalDecl<'_>> {
+    let keyword = |name, kind| value(kind, terminated(tag(name), multispace1));
+
+    spanned(pair(
+        ws(alt((
+            keyword("New", FinalKind::New),
+            keyword("Final", FinalKind::Final),
+            keyword("Empty", FinalKind::Empty),
+        ))),
+        terminated(ws(spanned(uc_ident_ns)), ws(char(';'))),
+    ))
+    .map(|((kind, ident), span)| FinalDecl {
+        kind,
+        identns: ident.into(),
+        docs: Docs::default(),
+        span,
+    })
+    .parse(input)
+}
+
+fn partial_app_declaration(input: &str) -> PResult<'_, PartialAppDecl<'_>> {
+    let name = || ws(spanned(alt((uc_ident_ns, lc_ident_ns))));
+
+    spanned(terminated(
+        alt((
+            // Vector<int>
+            pair(
+                name(),
+                delimited(
+                    ws(char('<')),
+                    pair(ws(term), many0(preceded(ws(char(',')), ws(term)))),
+                    ws(char('>')),
                 ),
-            ))),
-        ),
-        ws(eof),
-    )
-    .map(|(mut constr, mixs): (Vec<_>, Vec<Vec<_>>)| {
-        let len = mixs.iter().fold(0, |len, d| len + d.len());
-        constr.reserve(len);
-
-        mixs.into_iter()
-            .fold(constr, |mut decls: Vec<Declaration>, dec| {
-                decls.extend(dec);
-                decls
-            })
+            )
+            .map(|(ident, (term, mut terms))| {
+                terms.insert(0, term);
+                (ident, terms, true)
+            }),
+            // Vector int
+            pair(name(), many1(ws(term))).map(|(ident, terms)| (ident, terms, false)),
+        )),
+        ws(char(';')),
+    ))
+    .map(|((ident, terms, angle), span)| PartialAppDecl {
+        identns: ident.into(),
+        terms,
+        angle,
+        docs: Docs::default(),
+        span,
     })
     .parse(input)
 }
 
-pub fn schema(input: &str) -> IResult<&str, TLSchema> {
-    let (input, definitions) = tl_program(input)?;
+fn declaration(section: Section) -> impl FnMut(&str) -> PResult<'_, Declaration<'_>> {
+    move |input| {
+        alt((
+            combinator_declaration.map(|d| match section {
+                Section::Types => Declaration::Constr(d),
+                Section::Function => Declaration::Fun(d),
+            }),
+            builtin_declaration.map(Declaration::Builtin),
+            final_declaration.map(Declaration::Final),
+            partial_app_declaration.map(Declaration::PartialApp),
+        ))(input)
+    }
+}
 
-    let mut schema = TLSchema {
-        funcs: Vec::with_capacity(definitions.len() / 2),
-        constrs: Vec::with_capacity(definitions.len() / 2),
-        ..Default::default()
-    };
+/// Comments between two declarations, each with the whitespace before it.
+/// The whitespace before the next declaration is returned last.
+fn trivia(input: &str) -> PResult<'_, (Vec<(&str, &str)>, &str)> {
+    pair(many0(pair(multispace0, comments_inner)), multispace0)(input)
+}
+
+/// Reads the layer out of a `// LAYER 143` comment.
+fn layer_comment(comment: &str) -> Option<u32> {
+    comment.trim().strip_prefix("LAYER")?.trim().parse().ok()
+}
+
+fn is_banner_rule(comment: &str) -> bool {
+    !comment.is_empty() && comment.chars().all(|c| c == '/')
+}
+
+/// Builds the docs of the next declaration from the comments before it.
+/// `leading` is the whitespace after the previous item, if there is one.
+///
+/// The last block of comments not followed by a blank line becomes the doc
+/// comment, leaving out commented-out declarations. A block framed by lines
+/// of slashes is a section banner and sets `heading` for what follows.
+fn collect_docs<'a>(
+    comments: &[(&'a str, &'a str)],
+    leading: Option<&str>,
+    trailing: &str,
+    heading: &mut Option<&'a str>,
+) -> Vec<&'a str> {
+    let mut blocks: Vec<Vec<&'a str>> = vec![];
+
+    for (i, (space, comment)) in comments.iter().enumerate() {
+        if i == 0 && leading.is_some_and(|l| !l.contains('\n') && !space.contains('\n')) {
+            // trailing comment of the previous declaration
+            continue;
+        }
+        match blocks.last_mut() {
+            Some(block) if space.matches('\n').count() < 2 => block.push(comment),
+            _ => blocks.push(vec![comment]),
+        }
+    }
+
+    let adjacent = trailing.matches('\n').count() < 2;
+    let mut docs = vec![];
+    let last = blocks.len().saturating_sub(1);
+
+    for (i, block) in blocks.into_iter().enumerate() {
+        match block.as_slice() {
+            [first, titles @ .., end] if is_banner_rule(first) && is_banner_rule(end) => {
+                if let Some(title) = titles
+                    .iter()
+                    .map(|t| t.trim_start_matches('/').trim())
+                    .find(|t| !t.is_empty())
+                {
+                    *heading = Some(title);
+                }
+            }
+            _ if i == last && adjacent => {
+                docs = block
+                    .into_iter()
+                    .filter(|c| combinator_declaration(c).is_err())
+                    .map(|c| c.trim_start_matches('/').trim())
+                    .collect();
+            }
+            _ => (),
+        }
+    }
+
+    docs
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions<'f> {
+    /// File name reported in diagnostics.
+    pub file_name: Option<&'f str>,
+    /// Skip a broken declaration up to the next `;` and keep parsing, so
+    /// that every error in the schema gets reported at once.
+    pub recover: bool,
+}
+
+pub fn schema(input: &str) -> Result<TLSchema<'_>, ParsingError> {
+    schema_with_options(input, ParseOptions::default())
+}
+
+pub fn schema_with_options<'a>(
+    input: &'a str,
+    options: ParseOptions,
+) -> Result<TLSchema<'a>, ParsingError> {
+    let (schema, diagnostics) = schema_with_diagnostics(input, options);
+    if diagnostics.is_empty() {
+        Ok(schema)
+    } else {
+        Err(ParsingError::Parsing(diagnostics))
+    }
+}
+
+/// Parses as much of the schema as possible, returning the declarations
+/// that parsed along with the errors found. Without `options.recover`
+/// parsing stops at the first error.
+pub fn schema_with_diagnostics<'a>(
+    input: &'a str,
+    options: ParseOptions,
+) -> (TLSchema<'a>, Vec<Diagnostic>) {
+    let mut schema = TLSchema::default();
+    let mut diagnostics = vec![];
+    let mut section = Section::Types;
+    let mut rest = input;
+    let mut heading = None;
+    let index = LineIndex::new(input);
+
+    loop {
+        let consumed = &input[..input.len() - rest.len()];
+        let leading = (!consumed.is_empty()).then(|| &consumed[consumed.trim_end().len()..]);
+        // comments and whitespace never fail
+        let (comments, trailing) = match trivia(rest) {
+            Ok((r, trivia)) => {
+                rest = r;
+                trivia
+            }
+            Err(_) => (vec![], ""),
+        };
+        for (_, comment) in &comments {
+            match (schema.layer, layer_comment(comment)) {
+                (Some(layer), Some(other)) if layer != other => {
+                    let offset = input.offset(comment.trim_start());
+                    let message = format!("conflicting layer {}, after layer {}", other, layer);
+                    diagnostics.push(Diagnostic::new(input, offset, message, options.file_name));
+                }
+                (_, Some(layer)) => schema.layer = Some(layer),
+                _ => (),
+            }
+        }
+        if rest.is_empty() {
+            break;
+        }
+        let lines = collect_docs(&comments, leading, trailing, &mut heading);
+        let docs = Docs {
+            lines: lines.into_iter().map(Cow::from).collect(),
+            heading: heading.map(Cow::from),
+        };
+
+        if let Ok((r, s)) = alt((constr_sep, func_sep))(rest) {
+            section = s;
+            rest = r;
+            continue;
+        }
 
-    for dec in definitions {
-        match dec {
-            Declaration::Constr(d) => schema.constrs.push(d),
-            Declaration::Fun(d) => schema.funcs.push(d),
-            Declaration::Builtin(d) => schema.builtin.push(d),
+        match declaration(section)(rest) {
+            Ok((r, Declaration::Constr(mut d))) => {
+                index.resolve_spans(&mut d);
+                d.docs = docs;
+                schema.constrs.push(d);
+                rest = r;
+            }
+            Ok((r, Declaration::Fun(mut d))) => {
+                index.resolve_spans(&mut d);
+                d.docs = docs;
+                schema.funcs.push(d);
+                rest = r;
+            }
+            Ok((r, Declaration::Builtin(mut d))) => {
+                index.resolve_spans(&mut d);
+                d.docs = docs;
+                schema.builtin.push(d);
+                rest = r;
+            }
+            Ok((r, Declaration::PartialApp(mut d))) => {
+                index.resolve_spans(&mut d);
+                d.docs = docs;
+                schema.partial_apps.push(d);
+                rest = r;
+            }
+            Ok((r, Declaration::Final(mut d))) => {
+                index.resolve_spans(&mut d);
+                d.docs = docs;
+                schema.finals.push(d);
+                rest = r;
+            }
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+                diagnostics.push(Diagnostic::from_error(input, &e, options.file_name));
+                if !options.recover {
+                    break;
+                }
+                rest = e.input.find(';').map_or("", |p| &e.input[p + 1..]);
+            }
+            Err(nom::Err::Incomplete(_)) => {
+                let e = Error::from_error_kind(rest, nom::error::ErrorKind::Complete);
+                diagnostics.push(Diagnostic::from_error(input, &e, options.file_name));
+                break;
+            }
         }
     }
 
-    Ok((input, schema))
+    (schema, diagnostics)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{builtin_declaration, combinator_declaration, many0, var_ident, ws};
+    use super::{
+        builtin_declaration, combinator_declaration, many0, schema, schema_with_options, var_ident,
+        ws, ParseOptions,
+    };
     use crate::{
         basics::comments_inner,
+        errors::ParsingError,
+        span::Spanned,
         types::{
-            ArgBrack, ArgCond, ArgSingle, BuiltinDecl, CombinatorDecl, ConditionalDef, IdentNs,
-            OptArg, ResTypeNormal, Term,
+            Arg, ArgBrack, ArgCond, ArgSingle, BuiltinDecl, CombinatorDecl, ConditionalDef, Docs,
+            FinalKind, IdentNs, OptArg, ResType, ResTypeNormal, Term,
         },
     };
 
@@ -326,18 +575,22 @@ mod tests {
     #[test]
     fn test_builtin() {
         assert_eq!(
-            builtin_declaration("int ? = Int;"),
+            builtin_declaration("int ? = Int;").map(|(rest, d)| (rest, d.without_spans())),
             Ok((
                 "",
                 BuiltinDecl {
+                    docs: Default::default(),
+                    span: Default::default(),
                     identns: IdentNs {
+                        span: Default::default(),
                         namespace: None,
-                        name: "int"
+                        name: "int".into()
                     },
                     id: None,
                     res: IdentNs {
+                        span: Default::default(),
                         namespace: None,
-                        name: "Int"
+                        name: "Int".into()
                     }
                 }
             ))
@@ -347,34 +600,42 @@ mod tests {
     #[test]
     fn test_int128() {
         assert_eq!(
-            combinator_declaration("int128 4*[ int ] = Int128;"),
+            combinator_declaration("int128 4*[ int ] = Int128;")
+                .map(|(rest, d)| (rest, d.without_spans())),
             Ok((
                 "",
                 CombinatorDecl {
+                    docs: Default::default(),
+                    span: Default::default(),
                     identns: IdentNs {
+                        span: Default::default(),
                         namespace: None,
-                        name: "int128"
+                        name: "int128".into()
                     },
                     id: None,
                     opt_args: vec![],
                     args: vec![ArgBrack {
+                        span: Default::default(),
                         ident: None,
-                        mult: Some(4),
+                        mult: Some(Term::NatConst(4, Default::default())),
                         args: vec![ArgSingle {
+                            span: Default::default(),
                             excl: false,
                             term: Term::IdentNs(IdentNs {
+                                span: Default::default(),
                                 namespace: None,
-                                name: "int"
+                                name: "int".into()
                             })
-                            .into()
                         }
                         .into()],
                     }
                     .into()],
                     res: ResTypeNormal {
+                        span: Default::default(),
                         identns: IdentNs {
+                            span: Default::default(),
                             namespace: None,
-                            name: "Int128"
+                            name: "Int128".into()
                         },
                         terms: vec![]
                     }
@@ -387,17 +648,20 @@ mod tests {
     #[test]
     fn test_bytes() {
         assert_eq!(
-            combinator_declaration("bytes = Bytes;"),
+            combinator_declaration("bytes = Bytes;").map(|(rest, d)| (rest, d.without_spans())),
             Ok((
                 "",
                 CombinatorDecl {
+                    docs: Default::default(),
+                    span: Default::default(),
                     identns: IdentNs {
-                        name: "bytes",
+                        name: "bytes".into(),
                         ..Default::default()
                     },
                     res: ResTypeNormal {
+                        span: Default::default(),
                         identns: IdentNs {
-                            name: "Bytes",
+                            name: "Bytes".into(),
                             ..Default::default()
                         },
                         terms: vec![],
@@ -414,36 +678,42 @@ mod tests {
     #[test]
     fn test_vector() {
         assert_eq!(
-            combinator_declaration("vector#1cb5c415 {t:Type} # [ t ] = Vector t;"),
+            combinator_declaration("vector#1cb5c415 {t:Type} # [ t ] = Vector t;")
+                .map(|(rest, d)| (rest, d.without_spans())),
             Ok((
                 "",
                 CombinatorDecl {
+                    docs: Default::default(),
+                    span: Default::default(),
                     identns: IdentNs {
-                        name: "vector",
+                        name: "vector".into(),
                         ..Default::default()
                     },
                     id: Some(481674261),
                     opt_args: vec![OptArg {
-                        ident: "t",
+                        ident: "t".into(),
                         terms: vec![Term::IdentNs(IdentNs {
-                            name: "Type",
+                            name: "Type".into(),
                             ..Default::default()
                         })],
                         ..Default::default()
                     }],
                     args: vec![
                         ArgSingle {
+                            span: Default::default(),
                             excl: false,
-                            term: Term::Nat
+                            term: Term::Nat(Default::default())
                         }
                         .into(),
                         ArgBrack {
+                            span: Default::default(),
                             ident: None,
                             mult: None,
                             args: vec![ArgSingle {
+                                span: Default::default(),
                                 excl: false,
                                 term: Term::IdentNs(IdentNs {
-                                    name: "t",
+                                    name: "t".into(),
                                     ..Default::default()
                                 })
                             }
@@ -452,12 +722,13 @@ mod tests {
                         .into()
                     ],
                     res: ResTypeNormal {
+                        span: Default::default(),
                         identns: IdentNs {
-                            name: "Vector",
+                            name: "Vector".into(),
                             ..Default::default()
                         },
                         terms: vec![Term::IdentNs(IdentNs {
-                            name: "t",
+                            name: "t".into(),
                             ..Default::default()
                         })],
                     }
@@ -470,41 +741,42 @@ mod tests {
     #[test]
     fn test_flag() {
         assert_eq!(
-            combinator_declaration("webViewMessageSent#c94511c flags:# msg_id:flags.0?InputBotInlineMessageID = WebViewMessageSent;"),
+            combinator_declaration("webViewMessageSent#c94511c flags:# msg_id:flags.0?InputBotInlineMessageID = WebViewMessageSent;").map(|(rest, d)| (rest, d.without_spans())),
             Ok((
                 "",
-                CombinatorDecl {
-                    identns: IdentNs {
-                        name:"webViewMessageSent",
+                CombinatorDecl { docs: Default::default(), span: Default::default(),
+                    identns: IdentNs { span: Default::default(),
+                        name:"webViewMessageSent".into(),
                         namespace: None
                     },
                     id: Some(211046684),
                     opt_args: vec![],
                     args: vec![
-                        ArgCond {
-                            ident: "flags",
+                        ArgCond { span: Default::default(),
+                            ident: "flags".into(),
                             cond: None,
                             excl: false,
-                            term: Term::Nat.into()
+                            term: Term::Nat(Default::default())
                         }.into(),
-                        ArgCond {
-                            ident: "msg_id",
+                        ArgCond { span: Default::default(),
+                            ident: "msg_id".into(),
                             cond: Some(ConditionalDef {
-                                ident: "flags",
-                                index: Some(0)
+                                ident: "flags".into(),
+                                index: Some(0),
+                                span: Default::default(),
                             }),
                             excl: false,
-                            term: Term::IdentNs(IdentNs {
+                            term: Term::IdentNs(IdentNs { span: Default::default(),
                                 namespace: None,
-                                name: "InputBotInlineMessageID"
+                                name: "InputBotInlineMessageID".into()
                             })
                         }.into(),
 
                     ],
-                    res: ResTypeNormal {
-                         identns: IdentNs {
+                    res: ResTypeNormal { span: Default::default(),
+                         identns: IdentNs { span: Default::default(),
                             namespace: None,
-                            name: "WebViewMessageSent"
+                            name: "WebViewMessageSent".into()
                         },
                         terms: vec![]
                     }.into()
@@ -517,4 +789,309 @@ mod tests {
     fn test_var_ident() {
         assert_eq!(var_ident("int ? = "), Ok((" ? = ", "int")))
     }
+
+    #[test]
+    fn test_diagnostic_position() {
+        let text = "int ? = Int;\nbar#2 = Bar;\nfoo#1 a:int = Foo\n\n";
+        let err = schema_with_options(
+            text,
+            ParseOptions {
+                file_name: Some("test.tl"),
+                recover: false,
+            },
+        )
+        .unwrap_err();
+
+        let diagnostics = match err {
+            ParsingError::Parsing(d) => d,
+            e => panic!("unexpected error {:?}", e),
+        };
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 3);
+        assert_eq!(diagnostics[0].column, 18);
+        assert_eq!(diagnostics[0].message, "expected ';'");
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "error: expected ';'\n --> test.tl:3:18\n  |\n3 | foo#1 a:int = Foo\n  |                  ^"
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_recover() {
+        let text = "int ? = Int;\nfoo#1 a:int = ;\nok#3 = Ok;\nbar#2 a:Int$ = Bar;\n---functions---\nbaz#4 = ;\n";
+
+        let diagnostics = match schema(text) {
+            Err(ParsingError::Parsing(d)) => d,
+            r => panic!("unexpected result {:?}", r),
+        };
+        assert_eq!(diagnostics.len(), 1);
+
+        let options = ParseOptions {
+            recover: true,
+            ..Default::default()
+        };
+        let diagnostics = match schema_with_options(text, options) {
+            Err(ParsingError::Parsing(d)) => d,
+            r => panic!("unexpected result {:?}", r),
+        };
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| (d.line, d.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, "expected result type"),
+                (4, "expected '='"),
+                (6, "expected result type"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let text = "int ? = Int;\n---types---\nfoo#1 {X:Type} a:flags.0?Vector<int> = Foo X;\n";
+        let schema = schema(text).unwrap();
+
+        let builtin = &schema.builtin[0];
+        assert_eq!(builtin.span.as_str(text), "int ? = Int;");
+        assert_eq!(builtin.res.span.as_str(text), "Int");
+
+        let decl = &schema.constrs[0];
+        assert_eq!(
+            decl.span.as_str(text),
+            "foo#1 {X:Type} a:flags.0?Vector<int> = Foo X;"
+        );
+        assert_eq!((decl.span.line, decl.span.column), (3, 1));
+        assert_eq!(decl.identns.span.as_str(text), "foo");
+        assert_eq!(decl.opt_args[0].span.as_str(text), "{X:Type}");
+
+        let arg = &decl.args[0];
+        assert_eq!(arg.span().as_str(text), "a:flags.0?Vector<int>");
+        assert_eq!((arg.span().line, arg.span().column), (3, 16));
+        match arg {
+            Arg::Cond(ArgCond {
+                term: Term::Ang(ang),
+                ..
+            }) => {
+                assert_eq!(ang.span.as_str(text), "Vector<int>");
+                assert_eq!(ang.term.span().as_str(text), "int");
+            }
+            a => panic!("unexpected arg {:?}", a),
+        }
+
+        assert_eq!(decl.res.span().as_str(text), "Foo X");
+        assert_eq!(decl.res.span().column, 40);
+    }
+
+    #[test]
+    fn test_term_spans() {
+        let text = "---types---\nbar#2 n:# a:n*[ int ] b:n.0?(Vector int) = Bar 3;\n";
+        let decl = &schema(text).unwrap().constrs[0];
+
+        match &decl.args[..] {
+            [Arg::Cond(n), Arg::Brack(a), Arg::Cond(b)] => {
+                assert_eq!(n.term.span().as_str(text), "#");
+                assert_eq!(a.mult.as_ref().unwrap().span().as_str(text), "n");
+                assert_eq!(b.cond.as_ref().unwrap().span.as_str(text), "n.0?");
+                assert_eq!(b.term.span().as_str(text), "(Vector int)");
+            }
+            args => panic!("unexpected args {:?}", args),
+        }
+        match &decl.res {
+            ResType::Normal(r) => assert_eq!(r.terms[0].span().as_str(text), "3"),
+            r => panic!("unexpected result type {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_spans_in_equality() {
+        let a = schema("---types---\nfoo#1 x:int = Foo;\n").unwrap();
+        let b = schema("---types---\n\nfoo#1  x:int = Foo;\n").unwrap();
+
+        assert_ne!(a, b);
+        assert!(a.eq_ignoring_spans(&b));
+    }
+
+    #[test]
+    fn test_docs() {
+        let text = "\
+///////////////////////////////
+/// Authorization key creation
+///////////////////////////////
+
+//@description A pair of numbers @first The first
+//-number @second The second number
+pair#1 first:int second:int = Pair; // trailing
+
+//old#2 = Pair;
+/* Not attached */
+
+other#3 = Pair;
+//old#4 = Pair;
+last#5 = Pair;
+";
+        let schema = schema(text).unwrap();
+        let docs = &schema.constrs[0].docs;
+
+        assert_eq!(docs.heading.as_deref(), Some("Authorization key creation"));
+        assert_eq!(
+            docs.lines,
+            vec![
+                "@description A pair of numbers @first The first",
+                "-number @second The second number"
+            ]
+        );
+        assert_eq!(docs.description().as_deref(), Some("A pair of numbers"));
+        assert_eq!(docs.param("first").as_deref(), Some("The first number"));
+        assert_eq!(docs.param("second").as_deref(), Some("The second number"));
+
+        let docs = &schema.constrs[1].docs;
+        assert_eq!(docs.heading.as_deref(), Some("Authorization key creation"));
+        assert!(docs.lines.is_empty());
+
+        assert!(schema.constrs[2].docs.lines.is_empty());
+
+        let docs = Docs {
+            lines: vec!["Plain text".into(), "on two lines".into()],
+            heading: None,
+        };
+        assert_eq!(
+            docs.description().as_deref(),
+            Some("Plain text on two lines")
+        );
+    }
+
+    #[test]
+    fn test_docs_param_description() {
+        let text = "\
+//@description A chat photo @param_description What the photo shows @id Photo ID
+chatPhoto#1 id:long description:string = ChatPhoto;
+//@description A sticker
+sticker#2 description:string = Sticker;
+";
+        let schema = schema(text).unwrap();
+
+        let docs = &schema.constrs[0].docs;
+        assert_eq!(docs.description().as_deref(), Some("A chat photo"));
+        assert_eq!(
+            docs.param("description").as_deref(),
+            Some("What the photo shows")
+        );
+        assert_eq!(docs.param("id").as_deref(), Some("Photo ID"));
+        assert_eq!(docs.param("photo"), None);
+
+        let docs = &schema.constrs[1].docs;
+        assert_eq!(docs.description().as_deref(), Some("A sticker"));
+        assert_eq!(docs.param("description"), None);
+    }
+
+    #[test]
+    fn test_layer() {
+        let mut api = schema("a#1 = A;\n\n// LAYER 143\n").unwrap();
+        assert_eq!(api.layer, Some(143));
+
+        let mtproto = schema("b#2 = B;").unwrap();
+        assert_eq!(mtproto.layer, None);
+        api.extend(mtproto).unwrap();
+        assert_eq!(api.layer, Some(143));
+
+        let old = schema("c#3 = C;\n// LAYER 142").unwrap();
+        assert!(matches!(
+            api.extend(old),
+            Err(ParsingError::LayerMismatch(143, 142))
+        ));
+
+        let same = schema("// LAYER 143\na#1 = A;\n// LAYER 143\n").unwrap();
+        assert_eq!(same.layer, Some(143));
+
+        let diagnostics = match schema("// LAYER 143\na#1 = A;\n// LAYER 144\n") {
+            Err(ParsingError::Parsing(d)) => d,
+            r => panic!("unexpected result {:?}", r),
+        };
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (3, 4));
+        assert_eq!(
+            diagnostics[0].message,
+            "conflicting layer 144, after layer 143"
+        );
+    }
+
+    #[test]
+    fn test_full_grammar() {
+        let text = "
+            coupleInt {alpha:Type} int alpha = CoupleInt alpha;
+            intHash {alpha:Type} (vector %(coupleInt alpha)) = IntHash alpha;
+            matrix {X:Type} {m n:#} a:m*[ n*[ X ] ] = Matrix X m n;
+            pair (x y:int) = Pair;
+            bits {n:#} data:n*[ %Bit ] = Bits n;
+            _#1234 x:%Foo list:Vector<%Message> = Anon %Foo;
+            Vector int;
+            vector<long>;
+            New Foo;
+            Final Bool;
+            Empty False;
+        ";
+        let schema = schema(text).unwrap();
+
+        let printed = schema
+            .constrs
+            .iter()
+            .map(|d| d.to_string())
+            .chain(schema.partial_apps.iter().map(|d| d.to_string()))
+            .chain(schema.finals.iter().map(|d| d.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            printed,
+            vec![
+                "coupleInt {alpha:Type} int alpha = CoupleInt alpha;",
+                "intHash {alpha:Type} (vector %(coupleInt alpha)) = IntHash alpha;",
+                "matrix {X:Type} {m n:#} a:m*[ n*[ X ] ] = Matrix X m n;",
+                "pair (x y:int) = Pair;",
+                "bits {n:#} data:n*[ %Bit ] = Bits n;",
+                "_#1234 x:%Foo list:Vector<%Message> = Anon %Foo;",
+                "Vector int;",
+                "vector<long>;",
+                "New Foo;",
+                "Final Bool;",
+                "Empty False;",
+            ]
+        );
+
+        assert_eq!(
+            schema.constrs[1].args[0].without_spans(),
+            Arg::Single(ArgSingle {
+                excl: false,
+                term: Term::Par(
+                    vec![
+                        Term::IdentNs(IdentNs::from((None, "vector"))),
+                        Term::Percent(Box::new(Term::Par(
+                            vec![
+                                Term::IdentNs(IdentNs::from((None, "coupleInt"))),
+                                Term::IdentNs(IdentNs::from((None, "alpha"))),
+                            ],
+                            Default::default(),
+                        ))),
+                    ],
+                    Default::default(),
+                ),
+                span: Default::default(),
+            })
+        );
+        match &schema.constrs[2].args[0] {
+            Arg::Brack(ArgBrack { ident, mult, .. }) => {
+                assert_eq!(ident.as_deref(), Some("a"));
+                assert_eq!(
+                    mult.as_ref().map(Spanned::without_spans),
+                    Some(Term::IdentNs(IdentNs::from((None, "m"))))
+                );
+            }
+            arg => panic!("unexpected {:?}", arg),
+        }
+        assert_eq!(schema.finals[1].kind, FinalKind::Final);
+        assert!(schema.partial_apps[1].angle);
+
+        assert!(super::schema(&schema.to_string())
+            .unwrap()
+            .eq_ignoring_spans(&schema));
+    }
 }
@@ -0,0 +1,613 @@
+use crate::types::{Arg, BuiltinDecl, CombinatorDecl, TLSchema};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclKind {
+    Constructor,
+    Function,
+    Builtin,
+    PartialApp,
+    Final,
+}
+
+impl DeclKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Constructor => "constructor",
+            Self::Function => "function",
+            Self::Builtin => "builtin",
+            Self::PartialApp => "partial application",
+            Self::Final => "final declaration",
+        }
+    }
+}
+
+/// A declaration present in only one of the two schemas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeclRef {
+    pub kind: DeclKind,
+    /// The name, or the whole declaration for partial applications and
+    /// `New`/`Final`/`Empty` declarations, like `Final Bool`.
+    pub name: String,
+    /// `None` for declarations without an ID of their own.
+    pub id: Option<u32>,
+}
+
+/// A declaration present in both schemas, under the same name or, if it was
+/// renamed, under the same ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeclChange {
+    pub kind: DeclKind,
+    /// Name in the new schema.
+    pub name: String,
+    /// Name in the old schema, if it was renamed.
+    pub old_name: Option<String>,
+    pub old_id: u32,
+    pub new_id: u32,
+    pub fields: Vec<FieldChange>,
+}
+
+/// Fields are matched by name. Types are written as in the schema and flags
+/// as `flags.N`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldChange {
+    Added {
+        name: String,
+        ty: String,
+        flag: Option<String>,
+    },
+    Removed {
+        name: String,
+        ty: String,
+        flag: Option<String>,
+    },
+    Retyped {
+        name: String,
+        old: String,
+        new: String,
+    },
+    FlagChanged {
+        name: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
+
+/// Differences between two versions of a schema, like two layers of api.tl.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaDiff {
+    pub added: Vec<DeclRef>,
+    pub removed: Vec<DeclRef>,
+    /// Declarations whose name, ID or fields changed.
+    pub changed: Vec<DeclChange>,
+}
+
+/// A field as compared by the diff: name, type and flag.
+struct FieldInfo {
+    name: String,
+    ty: String,
+    flag: Option<String>,
+}
+
+/// A declaration matched by name and ID: a combinator or a builtin.
+trait Matched {
+    fn name(&self) -> String;
+    fn id(&self) -> u32;
+    fn fields(&self) -> Vec<FieldInfo>;
+}
+
+impl Matched for CombinatorDecl<'_> {
+    fn name(&self) -> String {
+        self.identns.to_string()
+    }
+
+    fn id(&self) -> u32 {
+        self.get_id()
+    }
+
+    fn fields(&self) -> Vec<FieldInfo> {
+        self.args
+            .iter()
+            .map(|arg| match arg {
+                Arg::Cond(a) => FieldInfo {
+                    name: a.ident.to_string(),
+                    ty: format!("{}{}", if a.excl { "!" } else { "" }, a.term),
+                    flag: a.cond.as_ref().map(|c| c.to_string()),
+                },
+                // unnamed arguments stand for themselves
+                arg => FieldInfo {
+                    name: arg.to_string(),
+                    ty: arg.to_string(),
+                    flag: None,
+                },
+            })
+            .collect()
+    }
+}
+
+/// A builtin's result type is part of its ID, so a change to it shows as a
+/// change of ID.
+impl Matched for BuiltinDecl<'_> {
+    fn name(&self) -> String {
+        self.identns.to_string()
+    }
+
+    fn id(&self) -> u32 {
+        self.get_id()
+    }
+
+    fn fields(&self) -> Vec<FieldInfo> {
+        vec![]
+    }
+}
+
+fn diff_fields(old: &impl Matched, new: &impl Matched) -> Vec<FieldChange> {
+    let old_fields = old.fields();
+    let new_fields = new.fields();
+    let old_by_name = old_fields
+        .iter()
+        .map(|f| (f.name.as_str(), f))
+        .collect::<HashMap<_, _>>();
+    let new_names = new_fields
+        .iter()
+        .map(|f| f.name.as_str())
+        .collect::<HashSet<_>>();
+    let mut changes = vec![];
+
+    for old in &old_fields {
+        if !new_names.contains(old.name.as_str()) {
+            changes.push(FieldChange::Removed {
+                name: old.name.clone(),
+                ty: old.ty.clone(),
+                flag: old.flag.clone(),
+            });
+        }
+    }
+
+    for new in &new_fields {
+        match old_by_name.get(new.name.as_str()) {
+            None => changes.push(FieldChange::Added {
+                name: new.name.clone(),
+                ty: new.ty.clone(),
+                flag: new.flag.clone(),
+            }),
+            Some(old) => {
+                if old.ty != new.ty {
+                    changes.push(FieldChange::Retyped {
+                        name: new.name.clone(),
+                        old: old.ty.clone(),
+                        new: new.ty.clone(),
+                    });
+                }
+                if old.flag != new.flag {
+                    changes.push(FieldChange::FlagChanged {
+                        name: new.name.clone(),
+                        old: old.flag.clone(),
+                        new: new.flag.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    changes
+}
+
+fn diff_decls<T: Matched>(kind: DeclKind, old: &[T], new: &[T], diff: &mut SchemaDiff) {
+    let decl_ref = |decl: &T| DeclRef {
+        kind,
+        name: decl.name(),
+        id: Some(decl.id()),
+    };
+
+    // the first declaration of a name wins, as in the generated code
+    let mut old_names = HashMap::new();
+    let mut old_ids = HashMap::new();
+    for (i, decl) in old.iter().enumerate() {
+        old_names.entry(decl.name()).or_insert(i);
+        old_ids.entry(decl.id()).or_insert(i);
+    }
+    let mut new_decls = vec![];
+    let mut new_names = HashSet::new();
+    for decl in new {
+        let name = decl.name();
+        if new_names.insert(name.clone()) {
+            new_decls.push((name, decl));
+        }
+    }
+
+    // Declarations are matched by name. One whose name is new but whose ID
+    // belonged to a declaration gone from the new schema was renamed.
+    let mut matched = HashSet::new();
+    let mut matches = vec![];
+    for (name, decl) in &new_decls {
+        let old = old_names.get(name).copied().or_else(|| {
+            old_ids
+                .get(&decl.id())
+                .copied()
+                .filter(|&i| !new_names.contains(&old[i].name()) && !matched.contains(&i))
+        });
+        matched.extend(old);
+        matches.push(old);
+    }
+
+    for (i, decl) in old.iter().enumerate() {
+        if !matched.contains(&i) && !new_names.contains(&decl.name()) {
+            diff.removed.push(decl_ref(decl));
+        }
+    }
+
+    for ((name, decl), i) in new_decls.into_iter().zip(matches) {
+        let Some(old) = i.map(|i| &old[i]) else {
+            diff.added.push(decl_ref(decl));
+            continue;
+        };
+
+        let old_name = old.name();
+        let change = DeclChange {
+            kind,
+            old_name: (old_name != name).then_some(old_name),
+            name,
+            old_id: old.id(),
+            new_id: decl.id(),
+            fields: diff_fields(old, decl),
+        };
+        if change.old_name.is_some() || change.old_id != change.new_id || !change.fields.is_empty()
+        {
+            diff.changed.push(change);
+        }
+    }
+}
+
+/// Partial applications and `New`/`Final`/`Empty` declarations have no ID
+/// to match them by, so they're compared as a whole and can only be added or
+/// removed.
+fn diff_texts<T: fmt::Display>(kind: DeclKind, old: &[T], new: &[T], diff: &mut SchemaDiff) {
+    let text = |decl: &T| decl.to_string().trim_end_matches(';').to_string();
+    let decl_ref = |name| DeclRef {
+        kind,
+        name,
+        id: None,
+    };
+    let old = old.iter().map(text).collect::<HashSet<_>>();
+    let new = new.iter().map(text).collect::<HashSet<_>>();
+
+    let mut removed = old.difference(&new).cloned().collect::<Vec<_>>();
+    let mut added = new.difference(&old).cloned().collect::<Vec<_>>();
+    removed.sort();
+    added.sort();
+    diff.removed.extend(removed.into_iter().map(decl_ref));
+    diff.added.extend(added.into_iter().map(decl_ref));
+}
+
+impl<'a> TLSchema<'a> {
+    /// Compares this schema with a newer version of it. Declarations are
+    /// matched by name, and a new name keeping the ID of a removed
+    /// declaration is reported as a rename.
+    pub fn diff(&self, new: &TLSchema) -> SchemaDiff {
+        let mut diff = SchemaDiff::default();
+        diff_decls(DeclKind::Builtin, &self.builtin, &new.builtin, &mut diff);
+        diff_decls(
+            DeclKind::Constructor,
+            &self.constrs,
+            &new.constrs,
+            &mut diff,
+        );
+        diff_decls(DeclKind::Function, &self.funcs, &new.funcs, &mut diff);
+        diff_texts(
+            DeclKind::PartialApp,
+            &self.partial_apps,
+            &new.partial_apps,
+            &mut diff,
+        );
+        diff_texts(DeclKind::Final, &self.finals, &new.finals, &mut diff);
+        diff
+    }
+}
+
+fn id_value(id: u32) -> String {
+    format!("{:08x}", id)
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let decl_ref = |d: &DeclRef| {
+            json!({
+                "kind": d.kind.as_str(),
+                "name": d.name,
+                "id": d.id.map(id_value),
+            })
+        };
+
+        json!({
+            "added": self.added.iter().map(decl_ref).collect::<Vec<_>>(),
+            "removed": self.removed.iter().map(decl_ref).collect::<Vec<_>>(),
+            "changed": self.changed.iter().map(DeclChange::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl DeclChange {
+    fn to_json(&self) -> Value {
+        json!({
+            "kind": self.kind.as_str(),
+            "name": self.name,
+            "old_name": self.old_name,
+            "old_id": id_value(self.old_id),
+            "new_id": id_value(self.new_id),
+            "fields": self.fields.iter().map(FieldChange::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl FieldChange {
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Added { name, ty, flag } | Self::Removed { name, ty, flag } => {
+                let change = match self {
+                    Self::Added { .. } => "added",
+                    _ => "removed",
+                };
+                json!({
+                    "change": change,
+                    "name": name,
+                    "type": ty,
+                    "flag": flag,
+                })
+            }
+            Self::Retyped { name, old, new } => json!({
+                "change": "retyped",
+                "name": name,
+                "old": old,
+                "new": new,
+            }),
+            Self::FlagChanged { name, old, new } => json!({
+                "change": "flag",
+                "name": name,
+                "old": old,
+                "new": new,
+            }),
+        }
+    }
+}
+
+fn flagged(ty: &str, flag: &Option<String>) -> String {
+    match flag {
+        Some(flag) => format!("{}?{}", flag, ty),
+        None => ty.to_string(),
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (sign, decls) in [('+', &self.added), ('-', &self.removed)] {
+            for d in decls {
+                write!(f, "{} {} {}", sign, d.kind.as_str(), d.name)?;
+                if let Some(id) = d.id {
+                    write!(f, "#{:08x}", id)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        for c in &self.changed {
+            let old_name = c.old_name.as_ref().unwrap_or(&c.name);
+            write!(f, "~ {} {}#{:08x}", c.kind.as_str(), old_name, c.old_id)?;
+            if c.old_name.is_some() {
+                write!(f, " -> {}", c.name)?;
+            }
+            if c.old_id != c.new_id {
+                write!(f, " -> #{:08x}", c.new_id)?;
+            }
+            writeln!(f)?;
+
+            for field in &c.fields {
+                match field {
+                    FieldChange::Added { name, ty, flag } => {
+                        writeln!(f, "    + {}:{}", name, flagged(ty, flag))?
+                    }
+                    FieldChange::Removed { name, ty, flag } => {
+                        writeln!(f, "    - {}:{}", name, flagged(ty, flag))?
+                    }
+                    FieldChange::Retyped { name, old, new } => {
+                        writeln!(f, "    ~ {}: {} -> {}", name, old, new)?
+                    }
+                    FieldChange::FlagChanged { name, old, new } => writeln!(
+                        f,
+                        "    ~ {}: {} -> {}",
+                        name,
+                        old.as_deref().unwrap_or("no flag"),
+                        new.as_deref().unwrap_or("no flag")
+                    )?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeclChange, DeclKind, DeclRef, FieldChange};
+    use crate::combinators::schema;
+
+    #[test]
+    fn test_diff_schemas() {
+        let old = schema(
+            "
+            user#1 flags:# id:int name:flags.0?string photo:flags.1?Photo = User;
+            photo#2 id:long = Photo;
+            ---functions---
+            getUser#3 id:int = User;
+        ",
+        )
+        .unwrap();
+        let new = schema(
+            "
+            user#4 flags:# id:long name:flags.2?string bot:flags.3?true = User;
+            photo#2 id:long = Photo;
+            chat#5 id:long = Chat;
+            ---functions---
+            getChat#6 id:long = Chat;
+        ",
+        )
+        .unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.added,
+            vec![
+                DeclRef {
+                    kind: DeclKind::Constructor,
+                    name: "chat".to_string(),
+                    id: Some(5)
+                },
+                DeclRef {
+                    kind: DeclKind::Function,
+                    name: "getChat".to_string(),
+                    id: Some(6)
+                },
+            ]
+        );
+        assert_eq!(
+            diff.removed,
+            vec![DeclRef {
+                kind: DeclKind::Function,
+                name: "getUser".to_string(),
+                id: Some(3)
+            }]
+        );
+        assert_eq!(
+            diff.changed,
+            vec![DeclChange {
+                kind: DeclKind::Constructor,
+                name: "user".to_string(),
+                old_name: None,
+                old_id: 1,
+                new_id: 4,
+                fields: vec![
+                    FieldChange::Removed {
+                        name: "photo".to_string(),
+                        ty: "Photo".to_string(),
+                        flag: Some("flags.1".to_string())
+                    },
+                    FieldChange::Retyped {
+                        name: "id".to_string(),
+                        old: "int".to_string(),
+                        new: "long".to_string()
+                    },
+                    FieldChange::FlagChanged {
+                        name: "name".to_string(),
+                        old: Some("flags.0".to_string()),
+                        new: Some("flags.2".to_string())
+                    },
+                    FieldChange::Added {
+                        name: "bot".to_string(),
+                        ty: "true".to_string(),
+                        flag: Some("flags.3".to_string())
+                    },
+                ]
+            }]
+        );
+
+        assert_eq!(
+            diff.to_string(),
+            "\
++ constructor chat#00000005
++ function getChat#00000006
+- function getUser#00000003
+~ constructor user#00000001 -> #00000004
+    - photo:flags.1?Photo
+    ~ id: int -> long
+    ~ name: flags.0 -> flags.2
+    + bot:flags.3?true
+"
+        );
+        assert_eq!(
+            diff.to_json().to_string(),
+            concat!(
+                r#"{"added":[{"kind":"constructor","name":"chat","id":"00000005"},"#,
+                r#"{"kind":"function","name":"getChat","id":"00000006"}],"#,
+                r#""removed":[{"kind":"function","name":"getUser","id":"00000003"}],"#,
+                r#""changed":[{"kind":"constructor","name":"user","old_name":null,"old_id":"00000001","new_id":"00000004","fields":["#,
+                r#"{"change":"removed","name":"photo","type":"Photo","flag":"flags.1"},"#,
+                r#"{"change":"retyped","name":"id","old":"int","new":"long"},"#,
+                r#"{"change":"flag","name":"name","old":"flags.0","new":"flags.2"},"#,
+                r#"{"change":"added","name":"bot","type":"true","flag":"flags.3"}]}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_diff_renamed() {
+        let old = schema("a#1 x:int = A; b#2 = B; c#3 = C;").unwrap();
+        let new = schema("d#1 x:long = A; c#2 = B; e#3 = C;").unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.to_string(),
+            "\
++ constructor e#00000003
+- constructor b#00000002
+~ constructor a#00000001 -> d
+    ~ x: int -> long
+~ constructor c#00000003 -> #00000002
+"
+        );
+        assert_eq!(diff.changed[0].name, "d");
+        assert_eq!(diff.changed[0].old_name.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_diff_other_declarations() {
+        let old = schema(
+            "
+            int ? = Int;
+            long ? = Long;
+            vector {t:Type} # [ t ] = Vector t;
+            New Peer;
+            Vector int;
+        ",
+        )
+        .unwrap();
+        let new = schema(
+            "
+            int ? = Int;
+            long ? = Int64;
+            double ? = Double;
+            vector {t:Type} # [ t ] = Vector t;
+            Final Peer;
+            Vector<long>;
+        ",
+        )
+        .unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.to_string(),
+            "\
++ builtin double#2210c154
++ partial application Vector<long>
++ final declaration Final Peer
+- partial application Vector int
+- final declaration New Peer
+~ builtin long#22076cba -> #1a6de460
+"
+        );
+        assert_eq!(diff.added[1].id, None);
+    }
+
+    #[test]
+    fn test_diff_same_schema() {
+        let api = std::fs::read_to_string("../data/schema/api.tl").unwrap();
+        let schema = schema(&api).unwrap();
+        assert!(schema.diff(&schema).is_empty());
+    }
+}
@@ -1,7 +1,21 @@
+use crate::merge::Collision;
+use nom::error::{ContextError, ErrorKind, FromExternalError, ParseError};
+use std::fmt;
+
+pub type PResult<'a, O> = nom::IResult<&'a str, O, Error<'a>>;
+
 #[derive(Debug)]
 pub enum ParsingError {
     IO(std::io::Error),
-    Parsing,
+    Parsing(Vec<Diagnostic>),
+    /// Two schemas being merged declare different layers.
+    LayerMismatch(u32, u32),
+    /// A compiled `.tlo` schema couldn't be decoded.
+    Tlo(String),
+    /// A JSON schema couldn't be read.
+    Json(String),
+    /// Schemas being merged declare the same names differently.
+    Collisions(Vec<Collision>),
 }
 
 impl From<std::io::Error> for ParsingError {
@@ -10,8 +24,191 @@ impl From<std::io::Error> for ParsingError {
     }
 }
 
-impl From<nom::Err<nom::error::Error<&str>>> for ParsingError {
-    fn from(_: nom::Err<nom::error::Error<&str>>) -> Self {
-        Self::Parsing
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IO(err) => write!(f, "{}", err),
+            Self::Parsing(diagnostics) => {
+                for diagnostic in diagnostics {
+                    writeln!(f, "{}", diagnostic)?;
+                }
+                Ok(())
+            }
+            Self::LayerMismatch(layer, other) => {
+                write!(f, "schemas disagree on the layer: {} and {}", layer, other)
+            }
+            Self::Tlo(message) => write!(f, "invalid .tlo file: {}", message),
+            Self::Json(message) => write!(f, "invalid JSON schema: {}", message),
+            Self::Collisions(collisions) => {
+                for collision in collisions {
+                    writeln!(f, "{}", collision)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParsingError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Char(char),
+    Label(&'static str),
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Char(c) => write!(f, "'{}'", c),
+            Self::Label(l) => write!(f, "{}", l),
+        }
+    }
+}
+
+/// nom error that keeps the furthest position reached and what could have
+/// been parsed there, so alternatives report the most relevant failure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error<'a> {
+    pub input: &'a str,
+    pub kind: ErrorKind,
+    pub expected: Vec<Expected>,
+}
+
+impl<'a> Error<'a> {
+    pub fn message(&self) -> String {
+        match self.expected.as_slice() {
+            [] if self.kind == ErrorKind::Eof => "expected end of input".to_string(),
+            [] => match self.input.chars().next() {
+                Some(c) => format!("unexpected '{}'", c),
+                None => "unexpected end of input".to_string(),
+            },
+            [e] => format!("expected {}", e),
+            es => format!(
+                "expected one of {}",
+                es.iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl<'a> ParseError<&'a str> for Error<'a> {
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+        Self {
+            input,
+            kind,
+            expected: vec![],
+        }
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn from_char(input: &'a str, c: char) -> Self {
+        Self {
+            input,
+            kind: ErrorKind::Char,
+            expected: vec![Expected::Char(c)],
+        }
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        match self.input.len().cmp(&other.input.len()) {
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal => {
+                for e in other.expected {
+                    if !self.expected.contains(&e) {
+                        self.expected.push(e);
+                    }
+                }
+                self
+            }
+        }
+    }
+}
+
+impl<'a> ContextError<&'a str> for Error<'a> {
+    fn add_context(input: &'a str, ctx: &'static str, mut other: Self) -> Self {
+        // a label only describes the failure better if nothing was consumed
+        if input.len() == other.input.len() {
+            other.expected = vec![Expected::Label(ctx)];
+        }
+        other
+    }
+}
+
+impl<'a, E> FromExternalError<&'a str, E> for Error<'a> {
+    fn from_external_error(input: &'a str, kind: ErrorKind, _: E) -> Self {
+        Self::from_error_kind(input, kind)
+    }
+}
+
+/// A parsing error located in the schema source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: Option<String>,
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+    pub source_line: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(source: &str, offset: usize, message: String, file: Option<&str>) -> Self {
+        let line_start = source[..offset].rfind('\n').map_or(0, |p| p + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |p| offset + p);
+
+        Self {
+            file: file.map(str::to_string),
+            offset,
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            source_line: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_string(),
+            message,
+        }
+    }
+
+    /// Builds a diagnostic from a parser error. Errors found at the start of
+    /// a line are moved back to the end of the previous token, which is where
+    /// a missing `;` or `=` actually belongs.
+    pub fn from_error(source: &str, err: &Error, file: Option<&str>) -> Self {
+        let mut offset = source.len() - err.input.len();
+        let trimmed = source[..offset].trim_end();
+        if source[trimmed.len()..offset].contains('\n') {
+            offset = trimmed.len();
+        }
+
+        Self::new(source, offset, err.message(), file)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line_no = self.line.to_string();
+        let pad = " ".repeat(line_no.len());
+
+        writeln!(f, "error: {}", self.message)?;
+        writeln!(
+            f,
+            "{pad}--> {}:{}:{}",
+            self.file.as_deref().unwrap_or("<schema>"),
+            self.line,
+            self.column
+        )?;
+        writeln!(f, "{pad} |")?;
+        writeln!(f, "{line_no} | {}", self.source_line)?;
+        write!(f, "{pad} | {}^", " ".repeat(self.column - 1))
     }
 }
@@ -0,0 +1,242 @@
+use crate::types::{Arg, BuiltinDecl, CombinatorDecl, ResType, TLSchema, Term};
+use std::collections::HashMap;
+
+/// A declaration found in a [`SchemaIndex`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decl<'s, 'a> {
+    Constructor(&'s CombinatorDecl<'a>),
+    Function(&'s CombinatorDecl<'a>),
+    Builtin(&'s BuiltinDecl<'a>),
+}
+
+/// Lookup tables over a schema, built once so that queries don't scan every
+/// declaration.
+///
+/// Names are qualified, like `messages.sendMessage` or `Vector`. A
+/// declaration that repeats the id or name of an earlier one is only
+/// reachable through the list queries. Queries about types accept bare names
+/// too: `int` and `user` stand for `Int` and `User`.
+#[derive(Debug)]
+pub struct SchemaIndex<'s, 'a> {
+    schema: &'s TLSchema<'a>,
+    constructors: HashMap<u32, &'s CombinatorDecl<'a>>,
+    functions: HashMap<u32, &'s CombinatorDecl<'a>>,
+    names: HashMap<String, Decl<'s, 'a>>,
+    constructors_of: HashMap<String, Vec<&'s CombinatorDecl<'a>>>,
+    functions_returning: HashMap<String, Vec<&'s CombinatorDecl<'a>>>,
+    referenced_by: HashMap<String, Vec<&'s CombinatorDecl<'a>>>,
+}
+
+impl<'a> TLSchema<'a> {
+    pub fn index(&self) -> SchemaIndex<'_, 'a> {
+        SchemaIndex::new(self)
+    }
+}
+
+impl<'s, 'a> SchemaIndex<'s, 'a> {
+    pub fn new(schema: &'s TLSchema<'a>) -> Self {
+        let mut index = Self {
+            schema,
+            constructors: HashMap::new(),
+            functions: HashMap::new(),
+            names: HashMap::new(),
+            constructors_of: HashMap::new(),
+            functions_returning: HashMap::new(),
+            referenced_by: HashMap::new(),
+        };
+
+        for decl in &schema.builtin {
+            index
+                .names
+                .entry(decl.identns.to_string())
+                .or_insert(Decl::Builtin(decl));
+            index
+                .constructors_of
+                .entry(decl.res.to_string())
+                .or_default();
+        }
+
+        for decl in &schema.constrs {
+            index.constructors.entry(decl.get_id()).or_insert(decl);
+            index
+                .names
+                .entry(decl.identns.to_string())
+                .or_insert(Decl::Constructor(decl));
+            index
+                .constructors_of
+                .entry(decl.res.identns().to_string())
+                .or_default()
+                .push(decl);
+        }
+
+        for decl in &schema.funcs {
+            index.functions.entry(decl.get_id()).or_insert(decl);
+            index
+                .names
+                .entry(decl.identns.to_string())
+                .or_insert(Decl::Function(decl));
+        }
+
+        let mut functions_returning = HashMap::<_, Vec<_>>::new();
+        for decl in &schema.funcs {
+            let ty = index.type_key(&returned_type(&decl.res));
+            functions_returning.entry(ty).or_default().push(decl);
+        }
+        index.functions_returning = functions_returning;
+
+        let mut referenced_by = HashMap::<_, Vec<_>>::new();
+        for decl in &schema.constrs {
+            let mut names = vec![];
+            decl.args.iter().for_each(|a| arg_names(a, &mut names));
+            let mut types = names
+                .iter()
+                .map(|name| index.type_key(name))
+                .collect::<Vec<_>>();
+            types.sort();
+            types.dedup();
+
+            for ty in types {
+                referenced_by.entry(ty).or_default().push(decl);
+            }
+        }
+        index.referenced_by = referenced_by;
+
+        index
+    }
+
+    /// The name types are keyed by: bare names like `int` or `future_salt`
+    /// stand for their boxed type.
+    fn type_key(&self, ty: &str) -> String {
+        match self.names.get(ty) {
+            Some(Decl::Constructor(c)) => c.res.identns().to_string(),
+            Some(Decl::Builtin(b)) => b.res.to_string(),
+            _ => ty.to_string(),
+        }
+    }
+
+    pub fn schema(&self) -> &'s TLSchema<'a> {
+        self.schema
+    }
+
+    pub fn constructor(&self, id: u32) -> Option<&'s CombinatorDecl<'a>> {
+        self.constructors.get(&id).copied()
+    }
+
+    pub fn function(&self, id: u32) -> Option<&'s CombinatorDecl<'a>> {
+        self.functions.get(&id).copied()
+    }
+
+    /// Looks up a constructor, function or builtin by qualified name.
+    pub fn decl(&self, name: &str) -> Option<Decl<'s, 'a>> {
+        self.names.get(name).copied()
+    }
+
+    /// Constructors of a boxed type, like `User`, in declaration order.
+    pub fn constructors_of(&self, ty: &str) -> &[&'s CombinatorDecl<'a>] {
+        self.constructors_of.get(ty).map_or(&[], Vec::as_slice)
+    }
+
+    /// Functions returning `ty` or `Vector<ty>`.
+    pub fn functions_returning(&self, ty: &str) -> &[&'s CombinatorDecl<'a>] {
+        self.functions_returning
+            .get(&self.type_key(ty))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Constructors with a field of type `ty`, directly or as an argument of
+    /// another type, like `Vector<ty>`.
+    pub fn referenced_by(&self, ty: &str) -> &[&'s CombinatorDecl<'a>] {
+        self.referenced_by
+            .get(&self.type_key(ty))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+fn returned_type(res: &ResType) -> String {
+    match res {
+        ResType::Ang(r) if r.identns.name == "Vector" && r.terms.is_empty() => {
+            let mut names = vec![];
+            term_names(&r.term, &mut names);
+            names.pop().unwrap_or_else(|| r.identns.to_string())
+        }
+        res => res.identns().to_string(),
+    }
+}
+
+fn arg_names(arg: &Arg, names: &mut Vec<String>) {
+    match arg {
+        Arg::Cond(a) => term_names(&a.term, names),
+        Arg::Single(a) => term_names(&a.term, names),
+        Arg::Par(a) => term_names(&a.term, names),
+        Arg::Brack(a) => a.args.iter().for_each(|a| arg_names(a, names)),
+    }
+}
+
+fn term_names(term: &Term, names: &mut Vec<String>) {
+    match term {
+        Term::IdentNs(i) => names.push(i.to_string()),
+        Term::Ang(a) => {
+            names.push(a.identns.to_string());
+            std::iter::once(a.term.as_ref())
+                .chain(&a.terms)
+                .for_each(|t| term_names(t, names));
+        }
+        Term::Percent(t) | Term::Plus(t, ..) => term_names(t, names),
+        Term::Par(terms, _) => terms.iter().for_each(|t| term_names(t, names)),
+        Term::Nat(_) | Term::NatConst(..) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decl;
+    use crate::combinators::schema;
+
+    #[test]
+    fn test_schema_index() {
+        let schema = schema(
+            "
+            int ? = Int;
+            user#1 id:int = User;
+            userEmpty#2 = User;
+            chat#3 admins:Vector<User> creator:user = Chat;
+            ---functions---
+            users.getUsers#4 ids:Vector<int> = Vector<User>;
+            users.getFullUser#5 id:int = User;
+            users.getIds#6 = Vector<int>;
+            ",
+        )
+        .unwrap();
+        let index = schema.index();
+        let names = |decls: &[&crate::types::CombinatorDecl]| {
+            decls
+                .iter()
+                .map(|d| d.identns.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(index.constructor(2), Some(&schema.constrs[1]));
+        assert_eq!(index.constructor(4), None);
+        assert_eq!(index.function(4), Some(&schema.funcs[0]));
+        assert_eq!(
+            index.decl("users.getFullUser"),
+            Some(Decl::Function(&schema.funcs[1]))
+        );
+        assert_eq!(index.decl("int"), Some(Decl::Builtin(&schema.builtin[0])));
+        assert_eq!(index.decl("users.getChats"), None);
+
+        assert_eq!(names(index.constructors_of("User")), ["user", "userEmpty"]);
+        assert!(index.constructors_of("Int").is_empty());
+        assert_eq!(
+            names(index.functions_returning("User")),
+            ["users.getUsers", "users.getFullUser"]
+        );
+        assert_eq!(names(index.functions_returning("Int")), ["users.getIds"]);
+        assert_eq!(names(index.functions_returning("int")), ["users.getIds"]);
+        assert_eq!(names(index.referenced_by("User")), ["chat"]);
+        assert_eq!(names(index.referenced_by("Int")), ["user"]);
+        assert_eq!(names(index.referenced_by("int")), ["user"]);
+        assert_eq!(names(index.referenced_by("user")), ["chat"]);
+        assert_eq!(names(index.referenced_by("Vector")), ["chat"]);
+    }
+}
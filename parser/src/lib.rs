@@ -1,11 +1,23 @@
 pub mod basics;
 pub mod combinators;
+pub mod crc32;
+pub mod diff;
 pub mod errors;
+pub mod index;
+pub mod merge;
+pub mod printer;
+pub mod schema_json;
+pub mod span;
+pub mod tlo;
 pub mod types;
+pub mod validate;
 
 #[cfg(test)]
 mod tests {
     use super::combinators::schema;
+    use super::errors::ParsingError;
+    use super::span::Spanned;
+    use super::types::TLSchema;
     use std::fs::File;
     use std::io::Read;
 
@@ -15,7 +27,7 @@ mod tests {
         let mut schema_text = String::new();
         fs.read_to_string(&mut schema_text).unwrap();
 
-        let mut schema = schema(&schema_text).unwrap().1;
+        let mut schema = schema(&schema_text).unwrap();
         schema.calculate_ids();
     }
 
@@ -25,7 +37,91 @@ mod tests {
         let mut schema_text = String::new();
         fs.read_to_string(&mut schema_text).unwrap();
 
-        let mut schema = schema(&schema_text).unwrap().1;
+        let mut schema = schema(&schema_text).unwrap();
         schema.calculate_ids();
     }
+
+    #[test]
+    fn test_verify_api_ids() {
+        let mut fs = File::open("../data/schema/api.tl").unwrap();
+        let mut schema_text = String::new();
+        fs.read_to_string(&mut schema_text).unwrap();
+
+        let schema = schema(&schema_text).unwrap();
+        assert_eq!(schema.verify_ids(), vec![]);
+    }
+
+    #[test]
+    fn test_verify_mtproto_ids() {
+        let mut fs = File::open("../data/schema/mtproto.tl").unwrap();
+        let mut schema_text = String::new();
+        fs.read_to_string(&mut schema_text).unwrap();
+
+        let mut schema = schema(&schema_text).unwrap();
+        let mismatches = schema
+            .verify_ids()
+            .into_iter()
+            .map(|m| m.name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            mismatches,
+            vec!["ipPortSecret", "accessPointRule", "help.configSimple"]
+        );
+
+        schema.calculate_ids();
+        let id_of = |name| {
+            schema
+                .constrs
+                .iter()
+                .find(|d| d.identns.name == name)
+                .and_then(|d| d.id)
+        };
+        assert_eq!(id_of("vector"), Some(0x1cb5c415));
+        assert_eq!(id_of("int128"), Some(0x84ccf7b7));
+        assert_eq!(id_of("int256"), Some(0x7bedeb5b));
+        assert_eq!(schema.builtin[0].id, Some(0xa8509bda));
+    }
+
+    #[test]
+    fn test_load_owned_schema() {
+        fn load() -> TLSchema<'static> {
+            TLSchema::from_paths(["../data/schema/api.tl", "../data/schema/mtproto.tl"]).unwrap()
+        }
+
+        let schema = std::thread::spawn(load).join().unwrap();
+        assert!(schema.constrs.iter().any(|d| d.identns.name == "vector"));
+        assert!(schema.funcs.iter().any(|d| d.identns.name == "sendMessage"));
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        assert!(matches!(
+            TLSchema::from_path("../data/schema/missing.tl"),
+            Err(ParsingError::IO(_))
+        ));
+    }
+
+    #[test]
+    fn test_print_roundtrip() {
+        for path in ["../data/schema/api.tl", "../data/schema/mtproto.tl"] {
+            let schema = TLSchema::from_path(path).unwrap();
+            let printed = schema.to_string();
+            let reparsed = super::combinators::schema(&printed).unwrap();
+
+            assert_eq!(reparsed.without_spans(), schema.without_spans(), "{}", path);
+            assert_eq!(reparsed.to_string(), printed, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_validate_schema() {
+        let schema =
+            TLSchema::from_paths(["../data/schema/api.tl", "../data/schema/mtproto.tl"]).unwrap();
+        let errors = schema
+            .validate()
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(errors, Vec::<String>::new());
+    }
 }
@@ -0,0 +1,319 @@
+use crate::{
+    diff::DeclKind,
+    errors::ParsingError,
+    span::Spanned,
+    types::{CombinatorDecl, TLSchema},
+};
+use std::{collections::HashMap, fmt, path::Path, str::FromStr};
+
+/// What to do when two schemas declare the same name differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergePolicy {
+    /// Fail, listing every collision.
+    #[default]
+    Error,
+    /// The later declaration replaces the earlier one.
+    Override,
+    /// The earlier declaration is kept.
+    KeepFirst,
+}
+
+impl FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "override" => Ok(Self::Override),
+            "keep-first" => Ok(Self::KeepFirst),
+            s => Err(format!(
+                "unknown merge policy `{}`, expected `error`, `override` or `keep-first`",
+                s
+            )),
+        }
+    }
+}
+
+/// Two different declarations with the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    pub kind: DeclKind,
+    pub name: String,
+    /// The declarations, as TL source.
+    pub old: String,
+    pub new: String,
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} `{}` is declared as `{}` and `{}`",
+            self.kind.as_str(),
+            self.name,
+            self.old,
+            self.new
+        )
+    }
+}
+
+impl<'a> TLSchema<'a> {
+    /// Merges the declarations of `other` into this schema. Declarations
+    /// identical to one already present are dropped; those that share a name
+    /// with a different one are resolved by `policy` and returned.
+    ///
+    /// With [`MergePolicy::Error`] collisions fail the merge, and the schema
+    /// is left as it was.
+    pub fn merge(
+        &mut self,
+        mut other: Self,
+        policy: MergePolicy,
+    ) -> Result<Vec<Collision>, ParsingError> {
+        let layer = match (self.layer, other.layer) {
+            (Some(layer), Some(other)) if layer != other => {
+                return Err(ParsingError::LayerMismatch(layer, other))
+            }
+            (layer, other) => layer.or(other),
+        };
+
+        let mut collisions = vec![];
+        self.merge_decls(
+            &mut other,
+            &mut Merger::Check {
+                collisions: &mut collisions,
+            },
+        );
+        if policy == MergePolicy::Error && !collisions.is_empty() {
+            return Err(ParsingError::Collisions(collisions));
+        }
+
+        self.layer = layer;
+        self.merge_decls(&mut other, &mut Merger::Apply { policy });
+        Ok(collisions)
+    }
+
+    /// Merges the declarations of `other` into this schema, failing on any
+    /// collision, like [`TLSchema::merge`] with [`MergePolicy::Error`].
+    pub fn extend(&mut self, other: Self) -> Result<(), ParsingError> {
+        self.merge(other, MergePolicy::Error).map(drop)
+    }
+
+    fn merge_decls(&mut self, other: &mut Self, merger: &mut Merger) {
+        merger.merge(
+            DeclKind::Builtin,
+            &mut self.builtin,
+            &mut other.builtin,
+            |d| d.identns.to_string(),
+            |a, b| a.get_id() == b.get_id() && a.res.eq_ignoring_spans(&b.res),
+        );
+        merger.merge(
+            DeclKind::Constructor,
+            &mut self.constrs,
+            &mut other.constrs,
+            |d| d.identns.to_string(),
+            same_combinator,
+        );
+        merger.merge(
+            DeclKind::Function,
+            &mut self.funcs,
+            &mut other.funcs,
+            |d| d.identns.to_string(),
+            same_combinator,
+        );
+        merger.merge(
+            DeclKind::PartialApp,
+            &mut self.partial_apps,
+            &mut other.partial_apps,
+            |d| d.to_string(),
+            |_, _| true,
+        );
+        merger.merge(
+            DeclKind::Final,
+            &mut self.finals,
+            &mut other.finals,
+            |d| d.identns.to_string(),
+            |a, b| a.kind == b.kind,
+        );
+    }
+}
+
+impl TLSchema<'static> {
+    /// Reads, parses and merges several schema files in order, resolving
+    /// declarations that collide with `policy`.
+    pub fn from_paths_with_policy<I, P>(
+        paths: I,
+        policy: MergePolicy,
+    ) -> Result<(Self, Vec<Collision>), ParsingError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let mut schema = Self::default();
+        let mut collisions = vec![];
+        for path in paths {
+            collisions.extend(schema.merge(Self::from_path(path)?, policy)?);
+        }
+        Ok((schema, collisions))
+    }
+}
+
+fn same_combinator(a: &CombinatorDecl, b: &CombinatorDecl) -> bool {
+    a.get_id() == b.get_id() && a.canonical_repr() == b.canonical_repr()
+}
+
+/// A pass over the declarations of two schemas: one that only finds the
+/// collisions, then one that merges.
+enum Merger<'c> {
+    Check { collisions: &'c mut Vec<Collision> },
+    Apply { policy: MergePolicy },
+}
+
+impl Merger<'_> {
+    fn merge<T: fmt::Display>(
+        &mut self,
+        kind: DeclKind,
+        decls: &mut Vec<T>,
+        other: &mut Vec<T>,
+        key: impl Fn(&T) -> String,
+        same: impl Fn(&T, &T) -> bool,
+    ) {
+        let mut names = HashMap::new();
+        for (i, decl) in decls.iter().enumerate() {
+            names.entry(key(decl)).or_insert(i);
+        }
+
+        match self {
+            Self::Check { collisions } => {
+                // declarations `other` adds can collide with each other too
+                let mut added = HashMap::new();
+                for decl in other.iter() {
+                    let name = key(decl);
+                    let first = match names.get(&name) {
+                        Some(&i) => &decls[i],
+                        None => *added.entry(name.clone()).or_insert(decl),
+                    };
+                    if !same(first, decl) {
+                        collisions.push(Collision {
+                            kind,
+                            name,
+                            old: first.to_string(),
+                            new: decl.to_string(),
+                        });
+                    }
+                }
+            }
+            Self::Apply { policy } => {
+                for decl in other.drain(..) {
+                    let name = key(&decl);
+                    match names.get(&name) {
+                        None => {
+                            names.insert(name, decls.len());
+                            decls.push(decl);
+                        }
+                        Some(&i) if *policy == MergePolicy::Override && !same(&decls[i], &decl) => {
+                            decls[i] = decl
+                        }
+                        Some(_) => (),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Collision, MergePolicy};
+    use crate::{combinators::schema, diff::DeclKind, errors::ParsingError};
+
+    const BASE: &str = "
+        int ? = Int;
+        vector {t:Type} # [ t ] = Vector t;
+        user#1 id:int = User;
+        ---functions---
+        getUser#2 id:int = User;
+    ";
+    const EXTENSION: &str = "
+        int ? = Int;
+        vector {t:Type} # [ t ] = Vector t;
+        user#3 id:int name:string = User;
+        chat#4 id:int = Chat;
+    ";
+
+    fn collision() -> Collision {
+        Collision {
+            kind: DeclKind::Constructor,
+            name: "user".into(),
+            old: "user#1 id:int = User;".into(),
+            new: "user#3 id:int name:string = User;".into(),
+        }
+    }
+
+    #[test]
+    fn test_merge_policies() {
+        let names = |schema: &crate::types::TLSchema| {
+            schema
+                .constrs
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut merged = schema(BASE).unwrap();
+        let collisions = merged
+            .merge(schema(EXTENSION).unwrap(), MergePolicy::KeepFirst)
+            .unwrap();
+        assert_eq!(collisions, [collision()]);
+        assert_eq!(merged.builtin.len(), 1);
+        assert_eq!(
+            names(&merged),
+            [
+                "vector {t:Type} # [ t ] = Vector t;",
+                "user#1 id:int = User;",
+                "chat#4 id:int = Chat;"
+            ]
+        );
+
+        let mut merged = schema(BASE).unwrap();
+        merged
+            .merge(schema(EXTENSION).unwrap(), MergePolicy::Override)
+            .unwrap();
+        assert_eq!(
+            names(&merged),
+            [
+                "vector {t:Type} # [ t ] = Vector t;",
+                "user#3 id:int name:string = User;",
+                "chat#4 id:int = Chat;"
+            ]
+        );
+        assert_eq!(merged.funcs.len(), 1);
+    }
+
+    #[test]
+    fn test_merge_conflict() {
+        let mut merged = schema(BASE).unwrap();
+        match merged.merge(schema(EXTENSION).unwrap(), MergePolicy::Error) {
+            Err(ParsingError::Collisions(collisions)) => assert_eq!(collisions, [collision()]),
+            other => panic!("unexpected {:?}", other),
+        }
+        // `chat` doesn't collide, but isn't added either
+        assert_eq!(merged, schema(BASE).unwrap());
+        assert!(matches!(
+            merged.extend(schema(EXTENSION).unwrap()),
+            Err(ParsingError::Collisions(_))
+        ));
+
+        let mut merged = schema(BASE).unwrap();
+        let collisions = merged
+            .merge(schema(BASE).unwrap(), MergePolicy::Error)
+            .unwrap();
+        assert!(collisions.is_empty());
+        assert_eq!(merged, schema(BASE).unwrap());
+
+        assert_eq!(
+            "keep-first".parse::<MergePolicy>(),
+            Ok(MergePolicy::KeepFirst)
+        );
+        assert!("first".parse::<MergePolicy>().is_err());
+    }
+}
@@ -0,0 +1,367 @@
+use crate::types::{
+    Arg, ArgBrack, ArgCond, ArgPar, ArgSingle, BuiltinDecl, CombinatorDecl, ConditionalDef, Docs,
+    FinalDecl, FinalKind, IdentNs, OptArg, PartialAppDecl, ResType, ResTypeAng, ResTypeNormal,
+    TLSchema, Term, TermAng,
+};
+use std::fmt::{self, Display, Formatter};
+
+/// Writes `items` separated by `sep`.
+fn join<T: Display>(f: &mut Formatter<'_>, items: &[T], sep: &str) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(sep)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+fn excl(excl: bool) -> &'static str {
+    if excl {
+        "!"
+    } else {
+        ""
+    }
+}
+
+/// Prints the schema as TL source: builtins, constructors, partial
+/// applications and `New`/`Final`/`Empty` declarations under `---types---`,
+/// then functions under `---functions---`, one declaration per line with its
+/// doc comment above it, and the layer last.
+///
+/// Section headings are written as banners whenever they change. A heading
+/// can't be cleared once set, so declarations without one that follow a
+/// declaration with one pick it up when the output is parsed again.
+impl<'a> Display for TLSchema<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut heading = None;
+
+        writeln!(f, "---types---")?;
+        for decl in &self.builtin {
+            write_docs(f, &decl.docs, &mut heading)?;
+            writeln!(f, "{}", decl)?;
+        }
+        for decl in &self.constrs {
+            write_docs(f, &decl.docs, &mut heading)?;
+            writeln!(f, "{}", decl)?;
+        }
+        for decl in &self.partial_apps {
+            write_docs(f, &decl.docs, &mut heading)?;
+            writeln!(f, "{}", decl)?;
+        }
+        for decl in &self.finals {
+            write_docs(f, &decl.docs, &mut heading)?;
+            writeln!(f, "{}", decl)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "---functions---")?;
+        for decl in &self.funcs {
+            write_docs(f, &decl.docs, &mut heading)?;
+            writeln!(f, "{}", decl)?;
+        }
+
+        if let Some(layer) = self.layer {
+            writeln!(f)?;
+            writeln!(f, "// LAYER {}", layer)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_docs<'d>(
+    f: &mut Formatter<'_>,
+    docs: &'d Docs,
+    heading: &mut Option<&'d str>,
+) -> fmt::Result {
+    if let Some(title) = docs.heading.as_deref() {
+        if *heading != Some(title) {
+            let rule = "/".repeat(title.len() + 4);
+            writeln!(f)?;
+            writeln!(f, "{}\n/// {}\n{}", rule, title, rule)?;
+            writeln!(f)?;
+            *heading = Some(title);
+        }
+    }
+
+    write!(f, "{}", docs)
+}
+
+/// Prints the doc lines as `//` comments, each ending with a newline.
+impl<'a> Display for Docs<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match line.is_empty() {
+                true => writeln!(f, "//")?,
+                false => writeln!(f, "// {}", line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Display for CombinatorDecl<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.identns)?;
+        if let Some(id) = self.id {
+            write!(f, "#{:x}", id)?;
+        }
+        for opt_arg in &self.opt_args {
+            write!(f, " {}", opt_arg)?;
+        }
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        write!(f, " = {};", self.res)
+    }
+}
+
+impl<'a> Display for BuiltinDecl<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.identns)?;
+        if let Some(id) = self.id {
+            write!(f, "#{:x}", id)?;
+        }
+        write!(f, " ? = {};", self.res)
+    }
+}
+
+impl<'a> Display for PartialAppDecl<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.identns)?;
+        if self.angle {
+            write!(f, "<")?;
+            join(f, &self.terms, ",")?;
+            write!(f, ">;")
+        } else {
+            for term in &self.terms {
+                write!(f, " {}", term)?;
+            }
+            write!(f, ";")
+        }
+    }
+}
+
+impl Display for FinalKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FinalKind::New => "New",
+            FinalKind::Final => "Final",
+            FinalKind::Empty => "Empty",
+        })
+    }
+}
+
+impl<'a> Display for FinalDecl<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {};", self.kind, self.identns)
+    }
+}
+
+impl<'a> Display for OptArg<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{{{}", self.ident)?;
+        for ident in &self.idents {
+            write!(f, " {}", ident)?;
+        }
+        write!(f, ":{}", excl(self.excl))?;
+        join(f, &self.terms, " ")?;
+        write!(f, "}}")
+    }
+}
+
+impl<'a> Display for Arg<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Arg::Par(a) => a.fmt(f),
+            Arg::Brack(a) => a.fmt(f),
+            Arg::Cond(a) => a.fmt(f),
+            Arg::Single(a) => a.fmt(f),
+        }
+    }
+}
+
+impl<'a> Display for ArgPar<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "({}", self.ident)?;
+        for ident in &self.idents {
+            write!(f, " {}", ident)?;
+        }
+        write!(f, ":{}{})", excl(self.excl), self.term)
+    }
+}
+
+impl<'a> Display for ArgBrack<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(ident) = &self.ident {
+            write!(f, "{}:", ident)?;
+        }
+        if let Some(mult) = &self.mult {
+            write!(f, "{}*", mult)?;
+        }
+        write!(f, "[ ")?;
+        join(f, &self.args, " ")?;
+        write!(f, " ]")
+    }
+}
+
+impl<'a> Display for ArgCond<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.ident)?;
+        if let Some(cond) = &self.cond {
+            write!(f, "{}?", cond)?;
+        }
+        write!(f, "{}{}", excl(self.excl), self.term)
+    }
+}
+
+impl<'a> Display for ArgSingle<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", excl(self.excl), self.term)
+    }
+}
+
+impl<'a> Display for ConditionalDef<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ident)?;
+        if let Some(index) = self.index {
+            write!(f, ".{}", index)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Display for ResType<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ResType::Normal(r) => r.fmt(f),
+            ResType::Ang(r) => r.fmt(f),
+        }
+    }
+}
+
+impl<'a> Display for ResTypeNormal<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.identns)?;
+        for term in &self.terms {
+            write!(f, " {}", term)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Display for ResTypeAng<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}<{}", self.identns, self.term)?;
+        for term in &self.terms {
+            write!(f, ",{}", term)?;
+        }
+        write!(f, ">")
+    }
+}
+
+impl<'a> Display for Term<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Par(terms, _) => {
+                write!(f, "(")?;
+                join(f, terms, " ")?;
+                write!(f, ")")
+            }
+            Term::IdentNs(i) => i.fmt(f),
+            Term::Nat(_) => write!(f, "#"),
+            Term::NatConst(n, _) => write!(f, "{}", n),
+            Term::Plus(t, n, _) => write!(f, "{}+{}", t, n),
+            Term::Percent(t) => write!(f, "%{}", t),
+            Term::Ang(a) => a.fmt(f),
+        }
+    }
+}
+
+impl<'a> Display for TermAng<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}<{}", self.identns, self.term)?;
+        for term in &self.terms {
+            write!(f, ",{}", term)?;
+        }
+        write!(f, ">")
+    }
+}
+
+impl<'a> Display for IdentNs<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.namespace {
+            Some(ns) => write!(f, "{}.{}", ns, self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::combinators::schema;
+
+    #[test]
+    fn test_print_declarations() {
+        let text = "
+            vector {t:Type} # [ t ] = Vector t;
+            int128 4*[ int ] = Int128;
+            msgs_ack#62d6b459 msg_ids:Vector<long> = MsgsAck;
+            user#3ff6ecb0 flags:# self:flags.10?true bot_info_version:flags.14?int = User;
+            int ? = Int;
+        ";
+        let schema = schema(text).unwrap();
+
+        let printed = schema
+            .builtin
+            .iter()
+            .map(|d| d.to_string())
+            .chain(schema.constrs.iter().map(|d| d.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            printed,
+            vec![
+                "int ? = Int;",
+                "vector {t:Type} # [ t ] = Vector t;",
+                "int128 4*[ int ] = Int128;",
+                "msgs_ack#62d6b459 msg_ids:Vector<long> = MsgsAck;",
+                "user#3ff6ecb0 flags:# self:flags.10?true bot_info_version:flags.14?int = User;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_schema() {
+        let text = "
+///////////////////
+/// Messages
+///////////////////
+
+// @description A message
+// @id Message id
+message#1 id:int = Message;
+
+---functions---
+getMessage#2 id:int = Message;
+        ";
+        let schema = schema(text).unwrap();
+
+        assert_eq!(
+            schema.to_string(),
+            "---types---
+
+////////////
+/// Messages
+////////////
+
+// @description A message
+// @id Message id
+message#1 id:int = Message;
+
+---functions---
+getMessage#2 id:int = Message;
+"
+        );
+    }
+}
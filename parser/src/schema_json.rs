@@ -0,0 +1,238 @@
+//! Conversion between [`TLSchema`] and the JSON schema format Telegram
+//! publishes, with `constructors` and `methods` arrays of
+//! `{"id", "predicate" | "method", "params": [{"name", "type"}], "type"}`.
+//!
+//! The format only describes combinators: builtins, partial applications
+//! and `New`/`Final`/`Empty` declarations aren't exported, and `{X:Type}`
+//! arguments are left out. They are recovered on import from the result
+//! type and the `!X` parameters that use them.
+
+use crate::{
+    combinators::combinator_declaration,
+    errors::ParsingError,
+    span::Spanned,
+    types::{Arg, ArgBrack, CombinatorDecl, TLSchema},
+};
+use nom::combinator::all_consuming;
+use serde_json::{json, Value};
+
+impl<'a> TLSchema<'a> {
+    /// Converts the constructors and functions of the schema to Telegram's
+    /// JSON schema format.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "constructors": decls_json(&self.constrs, "predicate"),
+            "methods": decls_json(&self.funcs, "method"),
+        })
+    }
+}
+
+impl TLSchema<'static> {
+    /// Reads a schema in Telegram's JSON schema format.
+    pub fn from_json(text: &str) -> Result<Self, ParsingError> {
+        let value = serde_json::from_str::<Value>(text)
+            .map_err(|err| ParsingError::Json(err.to_string()))?;
+
+        Ok(TLSchema {
+            constrs: decls_from_json(&value, "constructors", "predicate")?,
+            funcs: decls_from_json(&value, "methods", "method")?,
+            ..Default::default()
+        })
+    }
+}
+
+fn decls_json(decls: &[CombinatorDecl], name_key: &str) -> Value {
+    let decls = decls.iter().map(|decl| {
+        let params = decl
+            .args
+            .iter()
+            .flat_map(params)
+            .map(|(name, ty)| json!({ "name": name, "type": ty }))
+            .collect::<Vec<_>>();
+
+        json!({
+            "id": (decl.get_id() as i32).to_string(),
+            name_key: decl.identns.to_string(),
+            "params": params,
+            "type": decl.res.to_string(),
+        })
+    });
+    Value::Array(decls.collect())
+}
+
+/// Names and types of the parameters an argument declares.
+fn params(arg: &Arg) -> Vec<(String, String)> {
+    let excl = |excl: bool| if excl { "!" } else { "" };
+
+    match arg {
+        Arg::Cond(a) => {
+            let cond = match &a.cond {
+                Some(cond) => format!("{}?", cond),
+                None => String::new(),
+            };
+            let ty = format!("{}{}{}", cond, excl(a.excl), a.term);
+            vec![(a.ident.to_string(), ty)]
+        }
+        Arg::Par(a) => std::iter::once(&a.ident)
+            .chain(&a.idents)
+            .map(|ident| (ident.to_string(), format!("{}{}", excl(a.excl), a.term)))
+            .collect(),
+        Arg::Single(a) => vec![(String::new(), a.to_string())],
+        Arg::Brack(a) => {
+            let brack = ArgBrack {
+                ident: None,
+                ..a.clone()
+            };
+            let name = a.ident.as_deref().unwrap_or_default();
+            vec![(name.to_string(), brack.to_string())]
+        }
+    }
+}
+
+fn decls_from_json(
+    value: &Value,
+    key: &str,
+    name_key: &str,
+) -> Result<Vec<CombinatorDecl<'static>>, ParsingError> {
+    let error = |message: String| ParsingError::Json(message);
+
+    let Some(decls) = value.get(key) else {
+        return Ok(vec![]);
+    };
+    let decls = decls
+        .as_array()
+        .ok_or_else(|| error(format!("`{}` is not an array", key)))?;
+
+    decls
+        .iter()
+        .map(|decl| {
+            let field = |key: &str| {
+                decl.get(key)
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| error(format!("declaration without a string `{}`", key)))
+            };
+            let name = field(name_key)?;
+            // ids are written as signed 32-bit numbers
+            let id = field("id")?
+                .parse::<i32>()
+                .map(|id| id as u32)
+                .map_err(|_| error(format!("invalid id of `{}`", name)))?;
+
+            let res = field("type")?;
+            // `{X:Type}` arguments are the variables of the result type, like
+            // `t` in `Vector t`, and those of `!X` parameters.
+            let mut opt_args = res
+                .split_whitespace()
+                .skip(1)
+                .filter(|t| t.starts_with(|c: char| c.is_ascii_lowercase()) && !t.contains('.'))
+                .collect::<Vec<_>>();
+            let mut args = String::new();
+            for param in decl
+                .get("params")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                let param_name = param
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let ty = param
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| error(format!("parameter of `{}` without a type", name)))?;
+
+                if let Some(var) = ty.strip_prefix('!') {
+                    if !opt_args.contains(&var) {
+                        opt_args.push(var);
+                    }
+                }
+                match param_name.is_empty() {
+                    true => args.push_str(&format!(" {}", ty)),
+                    false => args.push_str(&format!(" {}:{}", param_name, ty)),
+                }
+            }
+
+            let opt_args: String = opt_args
+                .iter()
+                .map(|v| format!(" {{{}:Type}}", v))
+                .collect();
+            let text = format!("{}#{:x}{}{} = {};", name, id, opt_args, args, res);
+            // the text is made up here, so its spans point nowhere
+            let decl = all_consuming(combinator_declaration)(&text)
+                .map(|(_, decl)| decl.into_owned().without_spans())
+                .ok();
+            decl.ok_or_else(|| error(format!("invalid declaration `{}`", text)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{combinators::schema, errors::ParsingError, span::Spanned, types::TLSchema};
+
+    #[test]
+    fn test_schema_to_json() {
+        let schema = schema(
+            "
+            user#3ff6ecb0 flags:# bot:flags.14?true id:long photos:Vector<Photo> = User;
+            ---functions---
+            invokeWithLayer#da9b0d0d {X:Type} layer:int query:!X = X;
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            schema.to_json().to_string(),
+            concat!(
+                r##"{"constructors":[{"id":"1073147056","predicate":"user","params":["##,
+                r##"{"name":"flags","type":"#"},{"name":"bot","type":"flags.14?true"},"##,
+                r##"{"name":"id","type":"long"},{"name":"photos","type":"Vector<Photo>"}],"##,
+                r##""type":"User"}],"methods":[{"id":"-627372787","method":"invokeWithLayer","##,
+                r##""params":[{"name":"layer","type":"int"},{"name":"query","type":"!X"}],"##,
+                r##""type":"X"}]}"##
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        for path in ["../data/schema/api.tl", "../data/schema/mtproto.tl"] {
+            let mut schema = TLSchema::from_path(path).unwrap();
+            schema.calculate_ids();
+
+            let imported = TLSchema::from_json(&schema.to_json().to_string()).unwrap();
+            assert_eq!(imported.to_json(), schema.to_json());
+
+            for (decl, imported) in schema
+                .constrs
+                .iter()
+                .chain(&schema.funcs)
+                .zip(imported.constrs.iter().chain(&imported.funcs))
+            {
+                assert_eq!(decl.identns.without_spans(), imported.identns);
+                assert_eq!(decl.opt_args.without_spans(), imported.opt_args);
+                assert_eq!(decl.args.without_spans(), imported.args);
+                assert_eq!(decl.res.without_spans(), imported.res);
+                assert_eq!(decl.id, imported.id);
+                assert_eq!(decl.gen_id(), imported.gen_id(), "{}", decl.identns);
+            }
+        }
+    }
+
+    #[test]
+    fn test_json_errors() {
+        assert!(matches!(
+            TLSchema::from_json(r#"{"constructors": [{"id": "1"}]}"#),
+            Err(ParsingError::Json(_))
+        ));
+        let err = TLSchema::from_json(
+            r#"{"methods": [{"id": "2", "method": "ping", "params": [], "type": "?"}]}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid JSON schema: invalid declaration `ping#2 = ?;`"
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use crate::types::{
+    Arg, BuiltinDecl, CombinatorDecl, ConditionalDef, Declaration, FinalDecl, IdentNs, OptArg,
+    PartialAppDecl, ResType, TLSchema, Term,
+};
+
+/// Location of a node in the schema source.
+///
+/// Spans take part in comparisons like any other field; use
+/// [`Spanned::eq_ignoring_spans`] to compare nodes parsed from different
+/// places.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    /// Byte offset of the first character.
+    pub start: usize,
+    /// Byte offset one past the last character.
+    pub end: usize,
+    /// 1-based line of `start`.
+    pub line: usize,
+    /// 1-based column of `start`, counted in characters.
+    pub column: usize,
+}
+
+impl Span {
+    /// Parsers only ever see a suffix of the source, so they record positions
+    /// as the length of the input left at `start` and `end`. These get turned
+    /// into offsets by [`LineIndex::resolve`] once the whole source is known.
+    pub(crate) fn from_remaining(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Text covered by the span in the source it was parsed from.
+    pub fn as_str<'s>(&self, source: &'s str) -> &'s str {
+        &source[self.start..self.end]
+    }
+}
+
+pub struct LineIndex<'s> {
+    source: &'s str,
+    line_starts: Vec<usize>,
+}
+
+impl<'s> LineIndex<'s> {
+    pub fn new(source: &'s str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(p, _)| p + 1))
+            .collect();
+
+        Self {
+            source,
+            line_starts,
+        }
+    }
+
+    /// Returns the 1-based line and column of a byte offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let column = self.source[self.line_starts[line]..offset].chars().count();
+
+        (line + 1, column + 1)
+    }
+
+    fn resolve(&self, span: &mut Span) {
+        span.start = self.source.len() - span.start;
+        span.end = self.source.len() - span.end;
+        (span.line, span.column) = self.line_col(span.start);
+    }
+
+    /// Turns the spans recorded while parsing `node` into offsets.
+    pub(crate) fn resolve_spans<N: Spanned>(&self, node: &mut N) {
+        node.visit_spans(&mut |span| self.resolve(span));
+    }
+}
+
+/// Nodes carrying spans, directly or through the nodes they contain.
+pub trait Spanned: Clone + PartialEq {
+    /// Calls `f` on every span of the node, outermost first.
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span));
+
+    /// A copy of the node with every span reset to the default.
+    fn without_spans(&self) -> Self {
+        let mut node = self.clone();
+        node.visit_spans(&mut |span| *span = Span::default());
+        node
+    }
+
+    /// Compares two nodes by structure alone, wherever they were parsed from.
+    fn eq_ignoring_spans(&self, other: &Self) -> bool {
+        self.without_spans() == other.without_spans()
+    }
+}
+
+impl<T: Spanned> Spanned for Vec<T> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        self.iter_mut().for_each(|node| node.visit_spans(f));
+    }
+}
+
+impl<'a> Spanned for TLSchema<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        self.funcs.visit_spans(f);
+        self.constrs.visit_spans(f);
+        self.builtin.visit_spans(f);
+        self.partial_apps.visit_spans(f);
+        self.finals.visit_spans(f);
+    }
+}
+
+impl<'a> Spanned for Declaration<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        match self {
+            Declaration::Fun(d) | Declaration::Constr(d) => d.visit_spans(f),
+            Declaration::Builtin(d) => d.visit_spans(f),
+            Declaration::PartialApp(d) => d.visit_spans(f),
+            Declaration::Final(d) => d.visit_spans(f),
+        }
+    }
+}
+
+impl<'a> Spanned for CombinatorDecl<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        f(&mut self.span);
+        self.identns.visit_spans(f);
+        self.opt_args.iter_mut().for_each(|a| a.visit_spans(f));
+        self.args.iter_mut().for_each(|a| a.visit_spans(f));
+        self.res.visit_spans(f);
+    }
+}
+
+impl<'a> Spanned for BuiltinDecl<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        f(&mut self.span);
+        self.identns.visit_spans(f);
+        self.res.visit_spans(f);
+    }
+}
+
+impl<'a> Spanned for PartialAppDecl<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        f(&mut self.span);
+        self.identns.visit_spans(f);
+        self.terms.iter_mut().for_each(|t| t.visit_spans(f));
+    }
+}
+
+impl<'a> Spanned for FinalDecl<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        f(&mut self.span);
+        self.identns.visit_spans(f);
+    }
+}
+
+impl<'a> Spanned for OptArg<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        f(&mut self.span);
+        self.terms.iter_mut().for_each(|t| t.visit_spans(f));
+    }
+}
+
+impl<'a> Spanned for Arg<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        match self {
+            Arg::Par(a) => {
+                f(&mut a.span);
+                a.term.visit_spans(f);
+            }
+            Arg::Brack(a) => {
+                f(&mut a.span);
+                if let Some(mult) = &mut a.mult {
+                    mult.visit_spans(f);
+                }
+                a.args.iter_mut().for_each(|a| a.visit_spans(f));
+            }
+            Arg::Cond(a) => {
+                f(&mut a.span);
+                if let Some(cond) = &mut a.cond {
+                    cond.visit_spans(f);
+                }
+                a.term.visit_spans(f);
+            }
+            Arg::Single(a) => {
+                f(&mut a.span);
+                a.term.visit_spans(f);
+            }
+        }
+    }
+}
+
+impl<'a> Spanned for ConditionalDef<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        f(&mut self.span);
+    }
+}
+
+impl<'a> Spanned for ResType<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        match self {
+            ResType::Normal(r) => {
+                f(&mut r.span);
+                r.identns.visit_spans(f);
+                r.terms.iter_mut().for_each(|t| t.visit_spans(f));
+            }
+            ResType::Ang(r) => {
+                f(&mut r.span);
+                r.identns.visit_spans(f);
+                r.term.visit_spans(f);
+                r.terms.iter_mut().for_each(|t| t.visit_spans(f));
+            }
+        }
+    }
+}
+
+impl<'a> Spanned for Term<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        match self {
+            Term::Par(terms, span) => {
+                f(span);
+                terms.iter_mut().for_each(|t| t.visit_spans(f));
+            }
+            Term::IdentNs(i) => i.visit_spans(f),
+            Term::Percent(t) => t.visit_spans(f),
+            Term::Plus(t, _, span) => {
+                f(span);
+                t.visit_spans(f);
+            }
+            Term::Ang(a) => {
+                f(&mut a.span);
+                a.identns.visit_spans(f);
+                a.term.visit_spans(f);
+                a.terms.iter_mut().for_each(|t| t.visit_spans(f));
+            }
+            Term::Nat(span) | Term::NatConst(_, span) => f(span),
+        }
+    }
+}
+
+impl<'a> Spanned for IdentNs<'a> {
+    fn visit_spans(&mut self, f: &mut impl FnMut(&mut Span)) {
+        f(&mut self.span);
+    }
+}
@@ -0,0 +1,1028 @@
+//! Reader for schemas compiled to the binary TL-object format (`.tlo`), as
+//! written by `tl-parser` and shipped by Telegram and TDLib.
+//!
+//! A `.tlo` file holds the types of the schema followed by its constructors
+//! and functions, all serialized as TL values. Type references are by id and
+//! variables by number, so names are resolved once the whole file is read.
+
+use crate::{
+    errors::ParsingError,
+    types::{
+        Arg, ArgBrack, ArgCond, ArgSingle, BuiltinDecl, CombinatorDecl, ConditionalDef, IdentNs,
+        OptArg, ResTypeNormal, TLSchema, Term, TermAng,
+    },
+};
+use std::{borrow::Cow, collections::HashMap, path::Path};
+
+const TLS_SCHEMA_V2: u32 = 0x3a2f9be2;
+const TLS_SCHEMA_V3: u32 = 0xe4a8604b;
+const TLS_SCHEMA_V4: u32 = 0x90ac88d7;
+
+const TLS_TYPE: u32 = 0x12eb4386;
+const TLS_COMBINATOR: u32 = 0x5c0a1ed5;
+const TLS_COMBINATOR_LEFT_BUILTIN: u32 = 0xcd211f63;
+const TLS_COMBINATOR_LEFT: u32 = 0x4c12c6d9;
+const TLS_COMBINATOR_RIGHT_V2: u32 = 0x2c064372;
+const TLS_ARG_V2: u32 = 0x29dfe61b;
+
+const TLS_EXPR_TYPE: u32 = 0xecc9da78;
+const TLS_EXPR_NAT: u32 = 0xdcb49bd8;
+const TLS_NAT_CONST: u32 = 0x8ce940b1;
+const TLS_NAT_VAR: u32 = 0x4e8a14f0;
+const TLS_TYPE_VAR: u32 = 0x0142ceae;
+const TLS_ARRAY: u32 = 0xd9fb20de;
+const TLS_TYPE_EXPR: u32 = 0xc1863d08;
+
+const FLAG_BARE: i32 = 1;
+/// The argument binds a variable, whose number follows the flags.
+const FLAG_HAS_VAR: i32 = 1 << 1;
+const FLAG_OPT_VAR: i32 = 1 << 17;
+const FLAG_EXCL: i32 = 1 << 18;
+const FLAG_OPT_FIELD: i32 = 1 << 20;
+
+/// Type ids of `#` and `Type`, which aren't listed among the types.
+const NAT_TYPE_ID: u32 = 0x70659eff;
+const TYPE_TYPE_ID: u32 = 0x2cecf817;
+
+/// Decodes a `.tlo` file into the declarations it was compiled from.
+///
+/// Comments aren't kept by the compiler, so the declarations have no docs,
+/// and the schema has no layer.
+pub fn schema(data: &[u8]) -> Result<TLSchema<'static>, ParsingError> {
+    let mut reader = Reader { data, pos: 0 };
+
+    let version = match reader.u32()? {
+        TLS_SCHEMA_V2 => 2,
+        TLS_SCHEMA_V3 => 3,
+        TLS_SCHEMA_V4 => 4,
+        magic => return Err(reader.error(format!("unknown schema magic {:#010x}", magic))),
+    };
+    let _version = reader.i32()?;
+    let _date = reader.i32()?;
+
+    let mut types = HashMap::new();
+    for _ in 0..reader.count()? {
+        let ty = reader.type_record()?;
+        types.insert(ty.id, ty);
+    }
+
+    let mut constrs = vec![];
+    for _ in 0..reader.count()? {
+        constrs.push(reader.combinator(version)?);
+    }
+    let mut funcs = vec![];
+    for _ in 0..reader.count()? {
+        funcs.push(reader.combinator(version)?);
+    }
+
+    if reader.pos != data.len() {
+        return Err(reader.error("trailing data after the functions".into()));
+    }
+
+    for constr in &constrs {
+        if let Some(ty) = types.get_mut(&constr.type_id) {
+            ty.constructors.push(constr.name.clone());
+        }
+    }
+
+    let names = Names { types: &types };
+    let mut schema = TLSchema::default();
+    for constr in constrs {
+        match constr.args {
+            None => schema.builtin.push(names.builtin(constr)?),
+            Some(_) => schema.constrs.push(names.combinator(constr)?),
+        }
+    }
+    for func in funcs {
+        schema.funcs.push(names.combinator(func)?);
+    }
+
+    Ok(schema)
+}
+
+impl TLSchema<'static> {
+    /// Reads and decodes a compiled `.tlo` schema file.
+    pub fn from_tlo_path<P: AsRef<Path>>(path: P) -> Result<Self, ParsingError> {
+        schema(&std::fs::read(path)?)
+    }
+}
+
+struct TypeRecord {
+    id: u32,
+    name: String,
+    /// Names of the constructors of the type, filled in once they're read.
+    constructors: Vec<String>,
+}
+
+struct Combinator {
+    id: u32,
+    name: String,
+    type_id: u32,
+    /// `None` for builtins, which have no left-hand side.
+    args: Option<Vec<RawArg>>,
+    result: Expr,
+}
+
+struct RawArg {
+    name: String,
+    flags: i32,
+    var: Option<u32>,
+    /// Variable number and bit of the flags field the argument depends on.
+    cond: Option<(u32, u32)>,
+    ty: Expr,
+}
+
+enum Expr {
+    Type {
+        id: u32,
+        bare: bool,
+        children: Vec<Expr>,
+    },
+    TypeVar(u32),
+    NatConst(u32),
+    /// Nat variable `var`, plus `diff`.
+    NatVar {
+        var: u32,
+        diff: u32,
+    },
+    Array {
+        mult: Box<Expr>,
+        args: Vec<RawArg>,
+    },
+}
+
+struct Reader<'d> {
+    data: &'d [u8],
+    pos: usize,
+}
+
+impl<'d> Reader<'d> {
+    fn error(&self, message: String) -> ParsingError {
+        ParsingError::Tlo(format!("{} at byte {}", message, self.pos))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'d [u8], ParsingError> {
+        match self.data.get(self.pos..self.pos + len) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => Err(self.error("unexpected end of file".into())),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32, ParsingError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, ParsingError> {
+        self.u32().map(|n| n as i32)
+    }
+
+    fn count(&mut self) -> Result<u32, ParsingError> {
+        let n = self.i32()?;
+        u32::try_from(n).map_err(|_| self.error(format!("negative count {}", n)))
+    }
+
+    fn expect(&mut self, magic: u32, what: &str) -> Result<(), ParsingError> {
+        match self.u32()? {
+            n if n == magic => Ok(()),
+            n => Err(self.error(format!("expected {}, found {:#010x}", what, n))),
+        }
+    }
+
+    /// Reads a TL `string`: a length byte, or 254 and three length bytes,
+    /// then the data, padded to a multiple of 4.
+    fn string(&mut self) -> Result<String, ParsingError> {
+        let (len, header) = match self.bytes(1)?[0] {
+            254 => {
+                let b = self.bytes(3)?;
+                (u32::from_le_bytes([b[0], b[1], b[2], 0]) as usize, 4)
+            }
+            255 => return Err(self.error("invalid string length".into())),
+            n => (n as usize, 1),
+        };
+        let bytes = self.bytes(len)?;
+        self.bytes((4 - (header + len) % 4) % 4)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error("invalid UTF-8 in name".into()))
+    }
+
+    fn type_record(&mut self) -> Result<TypeRecord, ParsingError> {
+        self.expect(TLS_TYPE, "a type")?;
+        let id = self.u32()?;
+        let name = self.string()?;
+        let _constructors_num = self.i32()?;
+        let _flags = self.i32()?;
+        let _arity = self.i32()?;
+        let _params_types = self.bytes(8)?;
+
+        Ok(TypeRecord {
+            id,
+            name,
+            constructors: vec![],
+        })
+    }
+
+    fn combinator(&mut self, version: u32) -> Result<Combinator, ParsingError> {
+        self.expect(TLS_COMBINATOR, "a combinator")?;
+        let id = self.u32()?;
+        let name = self.string()?;
+        let type_id = self.u32()?;
+        let _var_count = self.i32()?;
+
+        let args = match self.u32()? {
+            TLS_COMBINATOR_LEFT => Some(self.args()?),
+            TLS_COMBINATOR_LEFT_BUILTIN => None,
+            n => {
+                return Err(self.error(format!(
+                    "expected a combinator left-hand side, found {:#010x}",
+                    n
+                )))
+            }
+        };
+        self.expect(TLS_COMBINATOR_RIGHT_V2, "a combinator right-hand side")?;
+        let result = self.type_expr()?;
+
+        if version >= 3 {
+            let _flags = self.i32()?;
+        }
+
+        Ok(Combinator {
+            id,
+            name,
+            type_id,
+            args,
+            result,
+        })
+    }
+
+    fn args(&mut self) -> Result<Vec<RawArg>, ParsingError> {
+        let mut args = vec![];
+        for _ in 0..self.count()? {
+            self.expect(TLS_ARG_V2, "an argument")?;
+            let name = self.string()?;
+            let flags = self.i32()?;
+            let var = match flags & FLAG_HAS_VAR {
+                0 => None,
+                _ => Some(self.u32()?),
+            };
+            let cond = match flags & FLAG_OPT_FIELD {
+                0 => None,
+                _ => Some((self.u32()?, self.u32()?)),
+            };
+            let ty = self.type_expr()?;
+
+            args.push(RawArg {
+                name,
+                flags,
+                var,
+                cond,
+                ty,
+            });
+        }
+        Ok(args)
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParsingError> {
+        match self.u32()? {
+            TLS_EXPR_TYPE => self.type_expr(),
+            TLS_EXPR_NAT => self.nat_expr(),
+            n => Err(self.error(format!("expected an expression, found {:#010x}", n))),
+        }
+    }
+
+    fn type_expr(&mut self) -> Result<Expr, ParsingError> {
+        match self.u32()? {
+            TLS_TYPE_EXPR => {
+                let id = self.u32()?;
+                let flags = self.i32()?;
+                let arity = self.count()?;
+                let children = (0..arity).map(|_| self.expr()).collect::<Result<_, _>>()?;
+                Ok(Expr::Type {
+                    id,
+                    bare: flags & FLAG_BARE != 0,
+                    children,
+                })
+            }
+            TLS_TYPE_VAR => {
+                let var = self.u32()?;
+                let _flags = self.i32()?;
+                Ok(Expr::TypeVar(var))
+            }
+            TLS_ARRAY => {
+                let mult = Box::new(self.nat_expr()?);
+                let args = self.args()?;
+                Ok(Expr::Array { mult, args })
+            }
+            n => Err(self.error(format!("expected a type expression, found {:#010x}", n))),
+        }
+    }
+
+    fn nat_expr(&mut self) -> Result<Expr, ParsingError> {
+        match self.u32()? {
+            TLS_NAT_CONST => Ok(Expr::NatConst(self.u32()?)),
+            TLS_NAT_VAR => {
+                let diff = self.u32()?;
+                let var = self.u32()?;
+                Ok(Expr::NatVar { var, diff })
+            }
+            n => Err(self.error(format!("expected a nat expression, found {:#010x}", n))),
+        }
+    }
+}
+
+/// Turns ids and variable numbers back into names.
+struct Names<'t> {
+    types: &'t HashMap<u32, TypeRecord>,
+}
+
+/// Variables bound by the arguments of a combinator, by number. `None` for
+/// anonymous ones, like the `#` in `# [ t ]`.
+type Vars = HashMap<u32, Option<String>>;
+
+impl<'t> Names<'t> {
+    fn error(&self, decl: &str, message: String) -> ParsingError {
+        ParsingError::Tlo(format!("{} in `{}`", message, decl))
+    }
+
+    fn type_name(&self, decl: &str, id: u32) -> Result<&'t TypeRecord, ParsingError> {
+        self.types
+            .get(&id)
+            .ok_or_else(|| self.error(decl, format!("unknown type id {:#010x}", id)))
+    }
+
+    fn builtin(&self, constr: Combinator) -> Result<BuiltinDecl<'static>, ParsingError> {
+        let ty = self.type_name(&constr.name, constr.type_id)?;
+        Ok(BuiltinDecl {
+            identns: ident_ns(&constr.name),
+            id: Some(constr.id),
+            res: ident_ns(&ty.name),
+            ..Default::default()
+        })
+    }
+
+    fn combinator(&self, constr: Combinator) -> Result<CombinatorDecl<'static>, ParsingError> {
+        let decl = &constr.name;
+        let raw_args = constr.args.unwrap_or_default();
+
+        let mut vars = Vars::new();
+        collect_vars(&raw_args, &mut vars);
+
+        let mut opt_args = vec![];
+        let mut args = vec![];
+        for arg in &raw_args {
+            if arg.flags & FLAG_OPT_VAR != 0 {
+                opt_args.push(OptArg {
+                    ident: arg.name.clone().into(),
+                    idents: vec![],
+                    excl: arg.flags & FLAG_EXCL != 0,
+                    terms: vec![self.term(decl, &arg.ty, &vars)?],
+                    span: Default::default(),
+                });
+            } else {
+                args.push(self.arg(decl, arg, &vars)?);
+            }
+        }
+
+        let res = match self.term(decl, &constr.result, &vars)? {
+            Term::IdentNs(identns) => ResTypeNormal {
+                identns,
+                terms: vec![],
+                span: Default::default(),
+            },
+            Term::Ang(ang) => ResTypeNormal {
+                identns: ang.identns,
+                terms: std::iter::once(*ang.term).chain(ang.terms).collect(),
+                span: Default::default(),
+            },
+            term => {
+                return Err(self.error(decl, format!("invalid result type `{}`", term)));
+            }
+        };
+
+        Ok(CombinatorDecl {
+            identns: ident_ns(decl),
+            id: Some(constr.id),
+            opt_args,
+            args,
+            res: res.into(),
+            docs: Default::default(),
+            span: Default::default(),
+        })
+    }
+
+    fn arg(&self, decl: &str, arg: &RawArg, vars: &Vars) -> Result<Arg<'static>, ParsingError> {
+        let excl = arg.flags & FLAG_EXCL != 0;
+        let ident: Option<Cow<'static, str>> = match arg.name.is_empty() {
+            true => None,
+            false => Some(arg.name.clone().into()),
+        };
+
+        if let Expr::Array { mult, args } = &arg.ty {
+            // The multiplicity of `# [ t ]` is the anonymous `#` before it,
+            // which isn't written out.
+            let mult = match &**mult {
+                Expr::NatVar { var, diff: 0 } if matches!(vars.get(var), Some(None)) => None,
+                mult => Some(self.term(decl, mult, vars)?),
+            };
+            let args = args
+                .iter()
+                .map(|a| self.arg(decl, a, vars))
+                .collect::<Result<_, _>>()?;
+            return Ok(ArgBrack {
+                ident,
+                mult,
+                args,
+                span: Default::default(),
+            }
+            .into());
+        }
+
+        let term = self.term(decl, &arg.ty, vars)?;
+        let Some(ident) = ident else {
+            return Ok(ArgSingle {
+                excl,
+                term,
+                span: Default::default(),
+            }
+            .into());
+        };
+        let cond = match arg.cond {
+            Some((var, bit)) => Some(ConditionalDef {
+                ident: self.var(decl, var, vars)?.into(),
+                index: Some(bit),
+                span: Default::default(),
+            }),
+            None => None,
+        };
+
+        Ok(ArgCond {
+            ident,
+            cond,
+            excl,
+            term,
+            span: Default::default(),
+        }
+        .into())
+    }
+
+    fn var(&self, decl: &str, var: u32, vars: &Vars) -> Result<String, ParsingError> {
+        match vars.get(&var) {
+            Some(Some(name)) => Ok(name.clone()),
+            _ => Err(self.error(decl, format!("unknown variable {}", var))),
+        }
+    }
+
+    fn term(&self, decl: &str, expr: &Expr, vars: &Vars) -> Result<Term<'static>, ParsingError> {
+        match expr {
+            Expr::Type {
+                id: NAT_TYPE_ID, ..
+            } => Ok(Term::Nat(Default::default())),
+            Expr::Type {
+                id: TYPE_TYPE_ID, ..
+            } => Ok(Term::IdentNs(ident_ns("Type"))),
+            Expr::Type { id, bare, children } => {
+                let ty = self.type_name(decl, *id)?;
+                // A bare type is written as its constructor when it has only
+                // one, and as `%Type` otherwise.
+                let (name, percent) = match (bare, ty.constructors.as_slice()) {
+                    (false, _) => (&ty.name, false),
+                    (true, [constr]) => (constr, false),
+                    (true, _) => (&ty.name, true),
+                };
+
+                let mut children = children
+                    .iter()
+                    .map(|c| self.term(decl, c, vars))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let term = match children.is_empty() {
+                    true => Term::IdentNs(ident_ns(name)),
+                    false => TermAng {
+                        identns: ident_ns(name),
+                        term: Box::new(children.remove(0)),
+                        terms: children,
+                        span: Default::default(),
+                    }
+                    .into(),
+                };
+
+                Ok(match percent {
+                    true => Term::Percent(Box::new(term)),
+                    false => term,
+                })
+            }
+            Expr::TypeVar(var) | Expr::NatVar { var, diff: 0 } => {
+                Ok(Term::IdentNs(ident_ns(&self.var(decl, *var, vars)?)))
+            }
+            Expr::NatVar { var, diff } => {
+                let var = Term::IdentNs(ident_ns(&self.var(decl, *var, vars)?));
+                Ok(Term::Plus(Box::new(var), *diff, Default::default()))
+            }
+            Expr::NatConst(n) => Ok(Term::NatConst(*n, Default::default())),
+            Expr::Array { .. } => Err(self.error(decl, "array used as a type".into())),
+        }
+    }
+}
+
+fn collect_vars(args: &[RawArg], vars: &mut Vars) {
+    for arg in args {
+        if let Some(var) = arg.var {
+            let name = match arg.name.is_empty() {
+                true => None,
+                false => Some(arg.name.clone()),
+            };
+            vars.insert(var, name);
+        }
+        if let Expr::Array { args, .. } = &arg.ty {
+            collect_vars(args, vars);
+        }
+    }
+}
+
+fn ident_ns(name: &str) -> IdentNs<'static> {
+    let (namespace, name) = match name.split_once('.') {
+        Some((ns, name)) => (Some(ns.to_string().into()), name.to_string()),
+        None => (None, name.to_string()),
+    };
+    IdentNs {
+        namespace,
+        name: name.into(),
+        span: Default::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combinators::schema as parse;
+    use crate::merge::MergePolicy;
+    use crate::span::Spanned;
+    use crate::types::{BuiltinDecl, ResType};
+
+    /// Builds `.tlo` files the way `tl-parser` writes them.
+    #[derive(Default)]
+    struct Writer(Vec<u8>);
+
+    impl Writer {
+        fn int(&mut self, n: u32) -> &mut Self {
+            self.0.extend(n.to_le_bytes());
+            self
+        }
+
+        fn string(&mut self, s: &str) -> &mut Self {
+            self.0.push(s.len() as u8);
+            self.0.extend(s.as_bytes());
+            while !self.0.len().is_multiple_of(4) {
+                self.0.push(0);
+            }
+            self
+        }
+
+        fn ty(&mut self, id: u32, name: &str) -> &mut Self {
+            self.int(TLS_TYPE).int(id).string(name);
+            self.int(1).int(0).int(0).int(0).int(0)
+        }
+
+        fn combinator(&mut self, id: u32, name: &str, type_id: u32, args: u32) -> &mut Self {
+            self.int(TLS_COMBINATOR).int(id).string(name).int(type_id);
+            self.int(0).int(TLS_COMBINATOR_LEFT).int(args)
+        }
+
+        fn arg(&mut self, name: &str, flags: i32) -> &mut Self {
+            self.int(TLS_ARG_V2).string(name).int(flags as u32)
+        }
+
+        fn type_expr(&mut self, id: u32, flags: i32, arity: u32) -> &mut Self {
+            self.int(TLS_TYPE_EXPR).int(id).int(flags as u32).int(arity)
+        }
+
+        fn type_var(&mut self, var: u32) -> &mut Self {
+            self.int(TLS_TYPE_VAR).int(var).int(0)
+        }
+
+        /// Right-hand side of a combinator.
+        fn result(&mut self, id: u32, arity: u32) -> &mut Self {
+            self.int(TLS_COMBINATOR_RIGHT_V2).type_expr(id, 0, arity)
+        }
+    }
+
+    /// Compiles `schema` the way `tl-parser` lays out its output: types are
+    /// numbered by the CRC32 of their name, variables by the order they're
+    /// bound in, and bare types are flagged on the type they belong to.
+    fn encode(schema: &TLSchema) -> Vec<u8> {
+        let type_id = |name: &str| crate::crc32::crc32(name.as_bytes());
+        let mut types = Vec::<(String, u32)>::new();
+        let mut constr_types = HashMap::new();
+        let builtins = schema.builtin.iter().map(|b| (&b.identns, &b.res));
+        let constrs = schema.constrs.iter().map(|c| (&c.identns, c.res.identns()));
+        for (name, ty) in builtins.chain(constrs) {
+            let ty = ty.to_string();
+            constr_types.insert(name.to_string(), type_id(&ty));
+            match types.iter_mut().find(|(t, _)| *t == ty) {
+                Some((_, count)) => *count += 1,
+                None => types.push((ty, 1)),
+            }
+        }
+
+        let mut w = Writer::default();
+        w.int(TLS_SCHEMA_V4).int(2).int(0);
+        w.int(types.len() as u32);
+        for (name, count) in &types {
+            w.int(TLS_TYPE).int(type_id(name)).string(name);
+            w.int(*count).int(0).int(0).int(0).int(0);
+        }
+
+        let encoder = Encoder {
+            constr_types: &constr_types,
+            type_id: &type_id,
+        };
+        w.int((schema.builtin.len() + schema.constrs.len()) as u32);
+        for b in &schema.builtin {
+            let id = type_id(&b.res.to_string());
+            w.int(TLS_COMBINATOR)
+                .int(b.get_id())
+                .string(&b.identns.to_string());
+            w.int(id).int(0).int(TLS_COMBINATOR_LEFT_BUILTIN);
+            w.result(id, 0).int(0);
+        }
+        for c in &schema.constrs {
+            encoder.combinator(&mut w, c, type_id(&c.res.identns().to_string()));
+        }
+        w.int(schema.funcs.len() as u32);
+        for f in &schema.funcs {
+            encoder.combinator(&mut w, f, 0);
+        }
+        w.0
+    }
+
+    struct Encoder<'a> {
+        constr_types: &'a HashMap<String, u32>,
+        type_id: &'a dyn Fn(&str) -> u32,
+    }
+
+    /// Numbers of the variables bound so far, and of the last `#` without a
+    /// name, which is the multiplicity of a following `[ ... ]`.
+    #[derive(Default)]
+    struct EncodedVars {
+        named: HashMap<String, u32>,
+        anonymous: Option<u32>,
+        count: u32,
+    }
+
+    impl EncodedVars {
+        fn bind(&mut self, name: Option<&str>) -> u32 {
+            let var = self.count;
+            self.count += 1;
+            match name {
+                Some(name) => {
+                    self.named.insert(name.to_string(), var);
+                }
+                None => self.anonymous = Some(var),
+            }
+            var
+        }
+    }
+
+    impl Encoder<'_> {
+        fn combinator(&self, w: &mut Writer, decl: &CombinatorDecl, type_id: u32) {
+            let mut vars = EncodedVars::default();
+            let mut args = Writer::default();
+            let mut count = 0;
+            for opt_arg in &decl.opt_args {
+                for ident in std::iter::once(&opt_arg.ident).chain(&opt_arg.idents) {
+                    let flags = FLAG_OPT_VAR | FLAG_HAS_VAR | excl_flag(opt_arg.excl);
+                    args.arg(ident, flags).int(vars.bind(Some(ident)));
+                    self.type_expr(&mut args, &opt_arg.terms[0], &vars, false);
+                    count += 1;
+                }
+            }
+            for arg in &decl.args {
+                self.arg(&mut args, arg, &mut vars);
+                count += 1;
+            }
+
+            w.int(TLS_COMBINATOR).int(decl.get_id());
+            w.string(&decl.identns.to_string())
+                .int(type_id)
+                .int(vars.count);
+            w.int(TLS_COMBINATOR_LEFT).int(count);
+            w.0.extend(args.0);
+
+            w.int(TLS_COMBINATOR_RIGHT_V2);
+            let (name, terms) = result_terms(&decl.res);
+            match terms.is_empty() {
+                true => self.type_expr(w, &Term::IdentNs(name), &vars, false),
+                false => {
+                    w.type_expr((self.type_id)(&name.to_string()), 0, terms.len() as u32);
+                    self.children(w, &terms, &vars);
+                }
+            }
+            w.int(0);
+        }
+
+        fn arg(&self, w: &mut Writer, arg: &Arg, vars: &mut EncodedVars) {
+            match arg {
+                Arg::Cond(a) => {
+                    let is_nat = matches!(a.term, Term::Nat(_));
+                    let mut flags = excl_flag(a.excl);
+                    if is_nat {
+                        flags |= FLAG_HAS_VAR;
+                    }
+                    if a.cond.is_some() {
+                        flags |= FLAG_OPT_FIELD;
+                    }
+                    w.arg(&a.ident, flags);
+                    if is_nat {
+                        w.int(vars.bind(Some(&a.ident)));
+                    }
+                    if let Some(cond) = &a.cond {
+                        w.int(vars.named[cond.ident.as_ref()])
+                            .int(cond.index.unwrap());
+                    }
+                    self.type_expr(w, &a.term, vars, false);
+                }
+                Arg::Single(a) => match a.term {
+                    Term::Nat(_) => {
+                        w.arg("", FLAG_HAS_VAR | excl_flag(a.excl));
+                        w.int(vars.bind(None)).type_expr(NAT_TYPE_ID, 0, 0);
+                    }
+                    _ => {
+                        w.arg("", excl_flag(a.excl));
+                        self.type_expr(w, &a.term, vars, false);
+                    }
+                },
+                Arg::Brack(a) => {
+                    w.arg(a.ident.as_deref().unwrap_or_default(), 0)
+                        .int(TLS_ARRAY);
+                    match &a.mult {
+                        Some(mult) => self.nat_expr(w, mult, vars),
+                        None => {
+                            w.int(TLS_NAT_VAR).int(0).int(vars.anonymous.unwrap());
+                        }
+                    }
+                    w.int(a.args.len() as u32);
+                    a.args.iter().for_each(|arg| self.arg(w, arg, vars));
+                }
+                Arg::Par(a) => panic!("`{}` is not supported", a),
+            }
+        }
+
+        fn type_expr(&self, w: &mut Writer, term: &Term, vars: &EncodedVars, bare: bool) {
+            let flags = if bare { FLAG_BARE } else { 0 };
+            match term {
+                Term::Nat(_) => {
+                    w.type_expr(NAT_TYPE_ID, flags, 0);
+                }
+                Term::IdentNs(i) if i.name == "Type" => {
+                    w.type_expr(TYPE_TYPE_ID, 0, 0);
+                }
+                Term::IdentNs(i) if vars.named.contains_key(i.name.as_ref()) => {
+                    w.type_var(vars.named[i.name.as_ref()]);
+                }
+                Term::IdentNs(i) => {
+                    let (id, flags) = match self.constr_types.get(&i.to_string()) {
+                        Some(id) => (*id, FLAG_BARE),
+                        None => ((self.type_id)(&i.to_string()), flags),
+                    };
+                    w.type_expr(id, flags, 0);
+                }
+                Term::Ang(a) => {
+                    let name = a.identns.to_string();
+                    let children = std::iter::once(&*a.term).chain(&a.terms);
+                    let children = children.cloned().collect::<Vec<_>>();
+                    match self.constr_types.get(&name) {
+                        Some(id) => w.type_expr(*id, FLAG_BARE, children.len() as u32),
+                        None => w.type_expr((self.type_id)(&name), flags, children.len() as u32),
+                    };
+                    self.children(w, &children, vars);
+                }
+                Term::Percent(t) => self.type_expr(w, t, vars, true),
+                term => panic!("`{}` is not a type", term),
+            }
+        }
+
+        fn children(&self, w: &mut Writer, terms: &[Term], vars: &EncodedVars) {
+            for term in terms {
+                match term {
+                    Term::NatConst(..) | Term::Plus(..) => {
+                        w.int(TLS_EXPR_NAT);
+                        self.nat_expr(w, term, vars);
+                    }
+                    Term::IdentNs(i) if vars.named.contains_key(i.name.as_ref()) => {
+                        w.int(TLS_EXPR_TYPE);
+                        w.type_var(vars.named[i.name.as_ref()]);
+                    }
+                    term => {
+                        w.int(TLS_EXPR_TYPE);
+                        self.type_expr(w, term, vars, false);
+                    }
+                }
+            }
+        }
+
+        fn nat_expr(&self, w: &mut Writer, term: &Term, vars: &EncodedVars) {
+            match term {
+                Term::NatConst(n, _) => {
+                    w.int(TLS_NAT_CONST).int(*n);
+                }
+                Term::IdentNs(i) => {
+                    w.int(TLS_NAT_VAR).int(0).int(vars.named[&*i.name]);
+                }
+                Term::Plus(t, n, _) => match &**t {
+                    Term::IdentNs(i) => {
+                        w.int(TLS_NAT_VAR).int(*n).int(vars.named[&*i.name]);
+                    }
+                    t => panic!("`{}` is not a nat variable", t),
+                },
+                term => panic!("`{}` is not a nat expression", term),
+            }
+        }
+    }
+
+    fn excl_flag(excl: bool) -> i32 {
+        if excl {
+            FLAG_EXCL
+        } else {
+            0
+        }
+    }
+
+    /// The name and arguments of a result type, `Vector<X>` and `Vector X`
+    /// alike.
+    fn result_terms<'a>(res: &ResType<'a>) -> (IdentNs<'a>, Vec<Term<'a>>) {
+        let (identns, terms) = match res {
+            ResType::Normal(r) => (&r.identns, r.terms.clone()),
+            ResType::Ang(r) => (
+                &r.identns,
+                std::iter::once(r.term.clone())
+                    .chain(r.terms.clone())
+                    .collect(),
+            ),
+        };
+        (identns.without_spans(), terms.without_spans())
+    }
+
+    /// Checks that `schema` survives being compiled and read back, except
+    /// for what `.tlo` files don't keep: docs, spans and the spelling of
+    /// result types.
+    fn assert_roundtrip(schema: &TLSchema) {
+        let read = super::schema(&encode(schema)).unwrap();
+
+        fn builtins(s: &TLSchema) -> Vec<(String, u32, String)> {
+            let builtin = |b: &BuiltinDecl| (b.identns.to_string(), b.get_id(), b.res.to_string());
+            s.builtin.iter().map(builtin).collect()
+        }
+        assert_eq!(builtins(&read), builtins(schema));
+        assert_eq!(read.constrs.len(), schema.constrs.len());
+        assert_eq!(read.funcs.len(), schema.funcs.len());
+
+        let decls = schema.constrs.iter().chain(&schema.funcs);
+        for (decl, read) in decls.zip(read.constrs.iter().chain(&read.funcs)) {
+            let name = decl.identns.to_string();
+            assert_eq!(read.identns, decl.identns.without_spans());
+            assert_eq!(read.id, Some(decl.get_id()), "{}", name);
+            assert_eq!(read.opt_args, decl.opt_args.without_spans(), "{}", name);
+            assert_eq!(read.args, decl.args.without_spans(), "{}", name);
+            assert_eq!(result_terms(&read.res), result_terms(&decl.res), "{}", name);
+        }
+    }
+
+    const INT: u32 = 0xa8509bda;
+    const STRING: u32 = 0xb5286e24;
+    const TRUE: u32 = 0x3fedd339;
+    const VECTOR: u32 = 0x1cb5c415;
+    const USER: u32 = 0x2da17977;
+
+    fn tlo() -> Vec<u8> {
+        let mut w = Writer::default();
+        w.int(TLS_SCHEMA_V4).int(0).int(0);
+
+        w.int(5);
+        w.ty(INT, "Int").ty(STRING, "String").ty(TRUE, "True");
+        w.ty(VECTOR, "Vector").ty(USER, "users.User");
+
+        w.int(5);
+        w.int(TLS_COMBINATOR)
+            .int(0xa8509bda)
+            .string("int")
+            .int(INT)
+            .int(0);
+        w.int(TLS_COMBINATOR_LEFT_BUILTIN).result(INT, 0).int(0);
+        w.int(TLS_COMBINATOR)
+            .int(0xb5286e24)
+            .string("string")
+            .int(STRING)
+            .int(0);
+        w.int(TLS_COMBINATOR_LEFT_BUILTIN).result(STRING, 0).int(0);
+
+        w.combinator(0x3fedd339, "true", TRUE, 0);
+        w.result(TRUE, 0).int(0);
+
+        // vector {t:Type} # [ t ] = Vector t;
+        w.combinator(0x1cb5c415, "vector", VECTOR, 3);
+        w.arg("t", FLAG_OPT_VAR | FLAG_HAS_VAR).int(0);
+        w.type_expr(TYPE_TYPE_ID, 0, 0);
+        w.arg("", FLAG_HAS_VAR).int(1).type_expr(NAT_TYPE_ID, 0, 0);
+        w.arg("", 0).int(TLS_ARRAY).int(TLS_NAT_VAR).int(0).int(1);
+        w.int(1).arg("", 0).type_var(0);
+        w.result(VECTOR, 1).int(TLS_EXPR_TYPE).type_var(0).int(0);
+
+        // users.user#1 flags:# bot:flags.0?true name:flags.3?string
+        //   ids:Vector<int> pairs:2*[ int string ] = users.User;
+        w.combinator(0x1, "users.user", USER, 5);
+        w.arg("flags", FLAG_HAS_VAR)
+            .int(0)
+            .type_expr(NAT_TYPE_ID, 0, 0);
+        w.arg("bot", FLAG_OPT_FIELD)
+            .int(0)
+            .int(0)
+            .type_expr(TRUE, FLAG_BARE, 0);
+        w.arg("name", FLAG_OPT_FIELD).int(0).int(3);
+        w.type_expr(STRING, FLAG_BARE, 0);
+        w.arg("ids", 0).type_expr(VECTOR, 0, 1);
+        w.int(TLS_EXPR_TYPE).type_expr(INT, FLAG_BARE, 0);
+        w.arg("pairs", 0)
+            .int(TLS_ARRAY)
+            .int(TLS_NAT_CONST)
+            .int(2)
+            .int(2);
+        w.arg("", 0).type_expr(INT, FLAG_BARE, 0);
+        w.arg("", 0).type_expr(STRING, FLAG_BARE, 0);
+        w.result(USER, 0).int(0);
+
+        // invokeWithLayer#2 {X:Type} layer:int query:!X = X;
+        w.int(1);
+        w.combinator(0x2, "invokeWithLayer", 0, 3);
+        w.arg("X", FLAG_OPT_VAR | FLAG_HAS_VAR).int(0);
+        w.type_expr(TYPE_TYPE_ID, 0, 0);
+        w.arg("layer", 0).type_expr(INT, FLAG_BARE, 0);
+        w.arg("query", FLAG_EXCL).type_var(0);
+        w.int(TLS_COMBINATOR_RIGHT_V2).type_var(0).int(0);
+
+        w.0
+    }
+
+    #[test]
+    fn test_read_tlo() {
+        let schema = schema(&tlo()).unwrap();
+        let expected = parse(
+            "
+            int#a8509bda ? = Int;
+            string#b5286e24 ? = String;
+            true#3fedd339 = True;
+            vector#1cb5c415 {t:Type} # [ t ] = Vector t;
+            users.user#1 flags:# bot:flags.0?true name:flags.3?string ids:Vector<int> pairs:2*[ int string ] = users.User;
+            ---functions---
+            invokeWithLayer#2 {X:Type} layer:int query:!X = X;
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(schema, expected.without_spans());
+    }
+
+    #[test]
+    fn test_read_compiled_schemas() {
+        // `bytes` is `string` under a different name, which the schemas
+        // use without declaring
+        let mut schema = parse("bytes ? = Bytes;").unwrap().into_owned();
+        for path in ["../data/schema/mtproto.tl", "../data/schema/api.tl"] {
+            let other = TLSchema::from_path(path).unwrap();
+            schema.merge(other, MergePolicy::Error).unwrap();
+        }
+        assert_roundtrip(&schema);
+    }
+
+    #[test]
+    fn test_read_nat_plus() {
+        let schema = parse(
+            "
+            int#a8509bda ? = Int;
+            tuple#1 {n:#} first:int rest:n+1*[ int ] = Tuple n;
+            ",
+        )
+        .unwrap();
+        assert_roundtrip(&schema);
+
+        match &super::schema(&encode(&schema)).unwrap().constrs[0].args[1] {
+            Arg::Brack(a) => assert_eq!(a.mult.as_ref().unwrap().to_string(), "n+1"),
+            arg => panic!("unexpected arg {:?}", arg),
+        }
+    }
+
+    #[test]
+    fn test_read_tlo_errors() {
+        let data = tlo();
+
+        let err = schema(&data[..data.len() - 4]).unwrap_err();
+        assert!(matches!(err, ParsingError::Tlo(_)), "{:?}", err);
+
+        let err = schema(&[0; 4]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid .tlo file: unknown schema magic 0x00000000 at byte 4"
+        );
+    }
+}
@@ -1,10 +1,21 @@
-use std::hash::Hash;
+use crate::{
+    combinators::{schema_with_options, ParseOptions},
+    crc32::crc32,
+    errors::ParsingError,
+    merge::MergePolicy,
+    span::Span,
+};
+use std::{borrow::Cow, hash::Hash, path::Path};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct TLSchema<'a> {
     pub funcs: Vec<CombinatorDecl<'a>>,
     pub constrs: Vec<CombinatorDecl<'a>>,
     pub builtin: Vec<BuiltinDecl<'a>>,
+    pub partial_apps: Vec<PartialAppDecl<'a>>,
+    pub finals: Vec<FinalDecl<'a>>,
+    /// Layer from the `// LAYER N` comment closing the schema, if any.
+    pub layer: Option<u32>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -12,6 +23,8 @@ pub enum Declaration<'a> {
     Fun(CombinatorDecl<'a>),
     Constr(CombinatorDecl<'a>),
     Builtin(BuiltinDecl<'a>),
+    PartialApp(PartialAppDecl<'a>),
+    Final(FinalDecl<'a>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -21,6 +34,8 @@ pub struct CombinatorDecl<'a> {
     pub opt_args: Vec<OptArg<'a>>,
     pub args: Vec<Arg<'a>>,
     pub res: ResType<'a>,
+    pub docs: Docs<'a>,
+    pub span: Span,
 }
 
 impl<'a> Hash for CombinatorDecl<'a> {
@@ -34,14 +49,59 @@ pub struct BuiltinDecl<'a> {
     pub identns: IdentNs<'a>,
     pub id: Option<u32>,
     pub res: IdentNs<'a>,
+    pub docs: Docs<'a>,
+    pub span: Span,
+}
+
+/// A type or combinator applied to some of its arguments, like `Vector int;`
+/// or `Vector<int>;`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct PartialAppDecl<'a> {
+    pub identns: IdentNs<'a>,
+    pub terms: Vec<Term<'a>>,
+    /// Arguments written between angle brackets.
+    pub angle: bool,
+    pub docs: Docs<'a>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum FinalKind {
+    /// The type has no constructors yet.
+    #[default]
+    New,
+    /// No constructors may be added to the type.
+    Final,
+    /// The type has no constructors and none may be added.
+    Empty,
+}
+
+/// `New T;`, `Final T;` or `Empty T;`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct FinalDecl<'a> {
+    pub kind: FinalKind,
+    pub identns: IdentNs<'a>,
+    pub docs: Docs<'a>,
+    pub span: Span,
+}
+
+/// Comments attached to a declaration.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
+pub struct Docs<'a> {
+    /// Comment lines directly above the declaration, without the comment markers.
+    pub lines: Vec<Cow<'a, str>>,
+    /// Title of the closest section banner above the declaration, like
+    /// `Authorization key creation`.
+    pub heading: Option<Cow<'a, str>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct OptArg<'a> {
-    pub ident: &'a str,
-    pub idents: Vec<&'a str>,
+    pub ident: Cow<'a, str>,
+    pub idents: Vec<Cow<'a, str>>,
     pub excl: bool,
     pub terms: Vec<Term<'a>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -56,29 +116,34 @@ pub enum Arg<'a> {
 pub struct ArgSingle<'a> {
     pub excl: bool,
     pub term: Term<'a>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct ArgPar<'a> {
-    pub ident: &'a str,
-    pub idents: Vec<&'a str>,
+    pub ident: Cow<'a, str>,
+    pub idents: Vec<Cow<'a, str>>,
     pub excl: bool,
     pub term: Term<'a>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct ArgBrack<'a> {
-    pub ident: Option<&'a str>,
-    pub mult: Option<i32>,
+    pub ident: Option<Cow<'a, str>>,
+    /// Multiplicity, like `4` in `4*[ int ]` or `n` in `n*[ int ]`.
+    pub mult: Option<Term<'a>>,
     pub args: Vec<Arg<'a>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct ArgCond<'a> {
-    pub ident: &'a str,
+    pub ident: Cow<'a, str>,
     pub cond: Option<ConditionalDef<'a>>,
     pub excl: bool,
     pub term: Term<'a>,
+    pub span: Span,
 }
 
 impl<'a> From<ArgBrack<'a>> for Arg<'a> {
@@ -107,8 +172,10 @@ impl<'a> From<ArgSingle<'a>> for Arg<'a> {
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct ConditionalDef<'a> {
-    pub ident: &'a str,
+    pub ident: Cow<'a, str>,
     pub index: Option<u32>,
+    /// Covers the whole condition, like `flags.0?`.
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -127,6 +194,7 @@ impl<'a> From<ResTypeAng<'a>> for ResType<'a> {
 pub struct ResTypeNormal<'a> {
     pub identns: IdentNs<'a>,
     pub terms: Vec<Term<'a>>,
+    pub span: Span,
 }
 
 impl<'a> From<ResTypeNormal<'a>> for ResType<'a> {
@@ -140,21 +208,25 @@ pub struct ResTypeAng<'a> {
     pub identns: IdentNs<'a>,
     pub term: Term<'a>,
     pub terms: Vec<Term<'a>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
 pub struct IdentNs<'a> {
-    pub namespace: Option<&'a str>,
-    pub name: &'a str,
+    pub namespace: Option<Cow<'a, str>>,
+    pub name: Cow<'a, str>,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Term<'a> {
-    Par(Vec<Term<'a>>),
+    /// Parenthesised terms; the span covers the parentheses.
+    Par(Vec<Term<'a>>, Span),
     IdentNs(IdentNs<'a>),
-    #[default]
-    Nat,
-    NatConst(u32),
+    Nat(Span),
+    NatConst(u32, Span),
+    /// A nat term plus a constant, like `n+1`.
+    Plus(Box<Term<'a>>, u32, Span),
     Percent(Box<Term<'a>>),
     Ang(TermAng<'a>),
 }
@@ -164,6 +236,13 @@ pub struct TermAng<'a> {
     pub identns: IdentNs<'a>,
     pub term: Box<Term<'a>>,
     pub terms: Vec<Term<'a>>,
+    pub span: Span,
+}
+
+impl<'a> Default for Term<'a> {
+    fn default() -> Self {
+        Self::Nat(Span::default())
+    }
 }
 
 impl<'a> From<TermAng<'a>> for Term<'a> {
@@ -172,18 +251,19 @@ impl<'a> From<TermAng<'a>> for Term<'a> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub enum Section {
     #[default]
     Types,
     Function,
 }
 
-impl<'a> From<(&'a str, Option<u32>)> for ConditionalDef<'a> {
-    fn from(r: (&'a str, Option<u32>)) -> Self {
+impl<'a> From<((&'a str, Option<u32>), Span)> for ConditionalDef<'a> {
+    fn from(r: ((&'a str, Option<u32>), Span)) -> Self {
         Self {
-            ident: r.0,
-            index: r.1,
+            ident: r.0 .0.into(),
+            index: r.0 .1,
+            span: r.1,
         }
     }
 }
@@ -191,8 +271,19 @@ impl<'a> From<(&'a str, Option<u32>)> for ConditionalDef<'a> {
 impl<'a> From<(Option<&'a str>, &'a str)> for IdentNs<'a> {
     fn from(r: (Option<&'a str>, &'a str)) -> Self {
         Self {
-            namespace: r.0,
-            name: r.1,
+            namespace: r.0.map(Cow::from),
+            name: r.1.into(),
+            span: Span::default(),
+        }
+    }
+}
+
+impl<'a> From<((Option<&'a str>, &'a str), Span)> for IdentNs<'a> {
+    fn from(r: ((Option<&'a str>, &'a str), Span)) -> Self {
+        Self {
+            namespace: r.0 .0.map(Cow::from),
+            name: r.0 .1.into(),
+            span: r.1,
         }
     }
 }
@@ -205,8 +296,265 @@ impl<'a> CombinatorDecl<'a> {
         }
     }
 
+    /// Computes the constructor ID as CRC32 of the declaration's canonical text.
     pub fn gen_id(&self) -> u32 {
-        0
+        crc32(self.canonical_repr().as_bytes())
+    }
+
+    /// Renders the declaration the way the TL spec normalises it before hashing:
+    /// no `#id`, `{X:Type}` written as `X:Type`, `Vector<t>` as `Vector t`,
+    /// `bytes` fields as `string` and `flags.N?true` fields dropped.
+    pub fn canonical_repr(&self) -> String {
+        let mut repr = self.identns.to_string();
+
+        for opt_arg in &self.opt_args {
+            repr.push(' ');
+            repr.push_str(&opt_arg.ident);
+            for ident in &opt_arg.idents {
+                repr.push(' ');
+                repr.push_str(ident);
+            }
+            repr.push(':');
+            if opt_arg.excl {
+                repr.push('!');
+            }
+            repr.push_str(&canonical_terms(&opt_arg.terms));
+        }
+
+        for arg in &self.args {
+            if arg.is_true_flag() {
+                continue;
+            }
+            repr.push(' ');
+            repr.push_str(&canonical_arg(arg));
+        }
+
+        repr.push_str(" = ");
+        repr.push_str(&match &self.res {
+            ResType::Normal(n) if n.terms.is_empty() => n.identns.to_string(),
+            ResType::Normal(n) => format!("{} {}", n.identns, canonical_terms(&n.terms)),
+            ResType::Ang(a) => format!(
+                "{} {}",
+                a.identns,
+                canonical_terms(std::iter::once(&a.term).chain(&a.terms))
+            ),
+        });
+
+        repr
+    }
+}
+
+impl<'a> BuiltinDecl<'a> {
+    pub fn get_id(&self) -> u32 {
+        match self.id {
+            Some(id) => id,
+            None => self.gen_id(),
+        }
+    }
+
+    pub fn gen_id(&self) -> u32 {
+        crc32(format!("{} ? = {}", self.identns, self.res).as_bytes())
+    }
+}
+
+impl<'a> Docs<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.heading.is_none()
+    }
+
+    /// Splits the comment into `@name text` annotations, where lines starting
+    /// with `-` continue the previous one. Text before the first annotation is
+    /// reported as `description`.
+    pub fn annotations(&self) -> Vec<(&str, String)> {
+        let mut annotations: Vec<(&str, String)> = vec![];
+
+        for line in &self.lines {
+            let line = line.strip_prefix('-').unwrap_or(line);
+            let mut parts = line.split('@');
+
+            let text = parts.next().unwrap_or_default().trim();
+            if !text.is_empty() {
+                match annotations.last_mut() {
+                    Some((_, value)) => {
+                        value.push(' ');
+                        value.push_str(text);
+                    }
+                    None => annotations.push(("description", text.to_string())),
+                }
+            }
+
+            for part in parts {
+                let (name, text) = part.split_once(char::is_whitespace).unwrap_or((part, ""));
+                annotations.push((name, text.trim().to_string()));
+            }
+        }
+
+        annotations
+    }
+
+    pub fn description(&self) -> Option<String> {
+        self.annotation("description")
+    }
+
+    /// Documentation of the field `name`, from `@param_<name>` or `@<name>`.
+    /// A field called `description` can only use the first form, as TDLib
+    /// does, since `@description` documents the declaration itself.
+    pub fn param(&self, name: &str) -> Option<String> {
+        let annotations = self.annotations();
+        let find = |key: &str| {
+            annotations
+                .iter()
+                .find(|(n, _)| *n == key)
+                .map(|(_, text)| text.clone())
+        };
+
+        find(&format!("param_{}", name)).or_else(|| match name {
+            "description" => None,
+            name => find(name),
+        })
+    }
+
+    fn annotation(&self, name: &str) -> Option<String> {
+        self.annotations()
+            .into_iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, text)| text)
+    }
+}
+
+impl<'a> Arg<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Arg::Par(a) => a.span,
+            Arg::Brack(a) => a.span,
+            Arg::Cond(a) => a.span,
+            Arg::Single(a) => a.span,
+        }
+    }
+
+    /// Returns true for bit-only fields like `flags.0?true`, which carry no data.
+    pub fn is_true_flag(&self) -> bool {
+        matches!(
+            self,
+            Arg::Cond(ArgCond {
+                cond: Some(_),
+                term: Term::IdentNs(i),
+                ..
+            }) if i.namespace.is_none() && i.name == "true"
+        )
+    }
+}
+
+fn canonical_arg(arg: &Arg) -> String {
+    match arg {
+        Arg::Cond(a) => {
+            let mut repr = format!("{}:", a.ident);
+            if let Some(cond) = &a.cond {
+                repr.push_str(&cond.ident);
+                if let Some(index) = cond.index {
+                    repr.push_str(&format!(".{}", index));
+                }
+                repr.push('?');
+            }
+            if a.excl {
+                repr.push('!');
+            }
+            match &a.term {
+                Term::IdentNs(i) if i.namespace.is_none() && i.name == "bytes" => {
+                    repr.push_str("string")
+                }
+                term => repr.push_str(&canonical_term(term)),
+            }
+            repr
+        }
+        Arg::Single(a) => {
+            let excl = if a.excl { "!" } else { "" };
+            format!("{}{}", excl, canonical_term(&a.term))
+        }
+        Arg::Brack(a) => {
+            let mut repr = String::new();
+            if let Some(ident) = &a.ident {
+                repr.push_str(&format!("{}:", ident));
+            }
+            if let Some(mult) = &a.mult {
+                repr.push_str(&format!("{}*", canonical_term(mult)));
+            }
+            repr.push_str("[ ");
+            for arg in &a.args {
+                repr.push_str(&canonical_arg(arg));
+                repr.push(' ');
+            }
+            repr.push(']');
+            repr
+        }
+        Arg::Par(a) => {
+            let excl = if a.excl { "!" } else { "" };
+            let idents = std::iter::once(&a.ident)
+                .chain(&a.idents)
+                .map(|i| i.as_ref())
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("({}:{}{})", idents, excl, canonical_term(&a.term))
+        }
+    }
+}
+
+fn canonical_terms<'a, 'b: 'a, I>(terms: I) -> String
+where
+    I: IntoIterator<Item = &'a Term<'b>>,
+{
+    terms
+        .into_iter()
+        .map(canonical_term)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn canonical_term(term: &Term) -> String {
+    match term {
+        Term::IdentNs(i) => i.to_string(),
+        Term::Nat(_) => "#".to_string(),
+        Term::NatConst(n, _) => n.to_string(),
+        Term::Plus(t, n, _) => format!("{}+{}", canonical_term(t), n),
+        Term::Percent(t) => format!("%{}", canonical_term(t)),
+        Term::Par(terms, _) => format!("({})", canonical_terms(terms)),
+        Term::Ang(a) => format!(
+            "{} {}",
+            a.identns,
+            canonical_terms(std::iter::once(a.term.as_ref()).chain(&a.terms))
+        ),
+    }
+}
+
+impl<'a> ResType<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            ResType::Normal(r) => r.span,
+            ResType::Ang(r) => r.span,
+        }
+    }
+
+    /// Name of the type, without its arguments.
+    pub fn identns(&self) -> &IdentNs<'a> {
+        match self {
+            ResType::Normal(r) => &r.identns,
+            ResType::Ang(r) => &r.identns,
+        }
+    }
+}
+
+impl<'a> Term<'a> {
+    /// Location of the term. A `%` term has the span of the term it marks bare.
+    pub fn span(&self) -> Span {
+        match self {
+            Term::IdentNs(i) => i.span,
+            Term::Ang(a) => a.span,
+            Term::Percent(t) => t.span(),
+            Term::Par(_, span)
+            | Term::Nat(span)
+            | Term::NatConst(_, span)
+            | Term::Plus(_, _, span) => *span,
+        }
     }
 }
 
@@ -217,12 +565,6 @@ impl<'a> IdentNs<'a> {
 }
 
 impl<'a> TLSchema<'a> {
-    pub fn extend(&mut self, other: Self) {
-        self.builtin.extend(other.builtin);
-        self.funcs.extend(other.funcs);
-        self.constrs.extend(other.constrs);
-    }
-
     pub fn calculate_ids(&mut self) {
         for decl in &mut self.constrs {
             match decl.id {
@@ -237,5 +579,253 @@ impl<'a> TLSchema<'a> {
                 None => decl.id = Some(decl.gen_id()),
             }
         }
+
+        for decl in &mut self.builtin {
+            match decl.id {
+                Some(_) => (),
+                None => decl.id = Some(decl.gen_id()),
+            }
+        }
+    }
+
+    /// Recomputes the ID of every declaration with an explicit `#id` and
+    /// reports the ones that don't match their canonical text.
+    pub fn verify_ids(&self) -> Vec<IdMismatch> {
+        self.constrs
+            .iter()
+            .chain(&self.funcs)
+            .filter_map(|decl| {
+                let declared = decl.id?;
+                let computed = decl.gen_id();
+                (declared != computed).then(|| IdMismatch {
+                    name: decl.identns.to_string(),
+                    declared,
+                    computed,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdMismatch {
+    pub name: String,
+    pub declared: u32,
+    pub computed: u32,
+}
+
+impl std::fmt::Display for IdMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: declared #{:08x}, computed #{:08x}",
+            self.name, self.declared, self.computed
+        )
+    }
+}
+
+fn owned(s: Cow<str>) -> Cow<'static, str> {
+    Cow::Owned(s.into_owned())
+}
+
+fn owned_all(v: Vec<Cow<str>>) -> Vec<Cow<'static, str>> {
+    v.into_iter().map(owned).collect()
+}
+
+impl<'a> TLSchema<'a> {
+    /// Copies every borrowed string, detaching the schema from its source text.
+    pub fn into_owned(self) -> TLSchema<'static> {
+        TLSchema {
+            funcs: self.funcs.into_iter().map(|d| d.into_owned()).collect(),
+            constrs: self.constrs.into_iter().map(|d| d.into_owned()).collect(),
+            builtin: self.builtin.into_iter().map(|d| d.into_owned()).collect(),
+            partial_apps: self
+                .partial_apps
+                .into_iter()
+                .map(|d| d.into_owned())
+                .collect(),
+            finals: self.finals.into_iter().map(|d| d.into_owned()).collect(),
+            layer: self.layer,
+        }
+    }
+}
+
+impl TLSchema<'static> {
+    /// Reads and parses a schema file, reporting every error found in it.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, ParsingError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let file_name = path.display().to_string();
+        let options = ParseOptions {
+            file_name: Some(&file_name),
+            recover: true,
+        };
+
+        Ok(schema_with_options(&text, options)?.into_owned())
+    }
+
+    /// Reads, parses and merges several schema files in order. Fails if
+    /// two files declare the same name differently.
+    pub fn from_paths<I, P>(paths: I) -> Result<Self, ParsingError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        Self::from_paths_with_policy(paths, MergePolicy::Error).map(|(schema, _)| schema)
+    }
+}
+
+impl<'a> CombinatorDecl<'a> {
+    pub fn into_owned(self) -> CombinatorDecl<'static> {
+        CombinatorDecl {
+            identns: self.identns.into_owned(),
+            id: self.id,
+            opt_args: self.opt_args.into_iter().map(|a| a.into_owned()).collect(),
+            args: self.args.into_iter().map(|a| a.into_owned()).collect(),
+            res: self.res.into_owned(),
+            docs: self.docs.into_owned(),
+            span: self.span,
+        }
+    }
+}
+
+impl<'a> BuiltinDecl<'a> {
+    pub fn into_owned(self) -> BuiltinDecl<'static> {
+        BuiltinDecl {
+            identns: self.identns.into_owned(),
+            id: self.id,
+            res: self.res.into_owned(),
+            docs: self.docs.into_owned(),
+            span: self.span,
+        }
+    }
+}
+
+impl<'a> PartialAppDecl<'a> {
+    pub fn into_owned(self) -> PartialAppDecl<'static> {
+        PartialAppDecl {
+            identns: self.identns.into_owned(),
+            terms: self.terms.into_iter().map(|t| t.into_owned()).collect(),
+            angle: self.angle,
+            docs: self.docs.into_owned(),
+            span: self.span,
+        }
+    }
+}
+
+impl<'a> FinalDecl<'a> {
+    pub fn into_owned(self) -> FinalDecl<'static> {
+        FinalDecl {
+            kind: self.kind,
+            identns: self.identns.into_owned(),
+            docs: self.docs.into_owned(),
+            span: self.span,
+        }
+    }
+}
+
+impl<'a> Docs<'a> {
+    pub fn into_owned(self) -> Docs<'static> {
+        Docs {
+            lines: owned_all(self.lines),
+            heading: self.heading.map(owned),
+        }
+    }
+}
+
+impl<'a> OptArg<'a> {
+    pub fn into_owned(self) -> OptArg<'static> {
+        OptArg {
+            ident: owned(self.ident),
+            idents: owned_all(self.idents),
+            excl: self.excl,
+            terms: self.terms.into_iter().map(|t| t.into_owned()).collect(),
+            span: self.span,
+        }
+    }
+}
+
+impl<'a> Arg<'a> {
+    pub fn into_owned(self) -> Arg<'static> {
+        match self {
+            Arg::Par(a) => Arg::Par(ArgPar {
+                ident: owned(a.ident),
+                idents: owned_all(a.idents),
+                excl: a.excl,
+                term: a.term.into_owned(),
+                span: a.span,
+            }),
+            Arg::Brack(a) => Arg::Brack(ArgBrack {
+                ident: a.ident.map(owned),
+                mult: a.mult.map(|t| t.into_owned()),
+                args: a.args.into_iter().map(|a| a.into_owned()).collect(),
+                span: a.span,
+            }),
+            Arg::Cond(a) => Arg::Cond(ArgCond {
+                ident: owned(a.ident),
+                cond: a.cond.map(|c| ConditionalDef {
+                    ident: owned(c.ident),
+                    index: c.index,
+                    span: c.span,
+                }),
+                excl: a.excl,
+                term: a.term.into_owned(),
+                span: a.span,
+            }),
+            Arg::Single(a) => Arg::Single(ArgSingle {
+                excl: a.excl,
+                term: a.term.into_owned(),
+                span: a.span,
+            }),
+        }
+    }
+}
+
+impl<'a> ResType<'a> {
+    pub fn into_owned(self) -> ResType<'static> {
+        match self {
+            ResType::Normal(r) => ResType::Normal(ResTypeNormal {
+                identns: r.identns.into_owned(),
+                terms: r.terms.into_iter().map(|t| t.into_owned()).collect(),
+                span: r.span,
+            }),
+            ResType::Ang(r) => ResType::Ang(ResTypeAng {
+                identns: r.identns.into_owned(),
+                term: r.term.into_owned(),
+                terms: r.terms.into_iter().map(|t| t.into_owned()).collect(),
+                span: r.span,
+            }),
+        }
+    }
+}
+
+impl<'a> Term<'a> {
+    pub fn into_owned(self) -> Term<'static> {
+        match self {
+            Term::Par(terms, span) => {
+                Term::Par(terms.into_iter().map(|t| t.into_owned()).collect(), span)
+            }
+            Term::IdentNs(i) => Term::IdentNs(i.into_owned()),
+            Term::Nat(span) => Term::Nat(span),
+            Term::NatConst(n, span) => Term::NatConst(n, span),
+            Term::Plus(t, n, span) => Term::Plus(Box::new(t.into_owned()), n, span),
+            Term::Percent(t) => Term::Percent(Box::new(t.into_owned())),
+            Term::Ang(a) => Term::Ang(TermAng {
+                identns: a.identns.into_owned(),
+                term: Box::new(a.term.into_owned()),
+                terms: a.terms.into_iter().map(|t| t.into_owned()).collect(),
+                span: a.span,
+            }),
+        }
+    }
+}
+
+impl<'a> IdentNs<'a> {
+    pub fn into_owned(self) -> IdentNs<'static> {
+        IdentNs {
+            namespace: self.namespace.map(owned),
+            name: owned(self.name),
+            span: self.span,
+        }
     }
 }
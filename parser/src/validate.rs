@@ -0,0 +1,514 @@
+use crate::{
+    errors::Diagnostic,
+    span::Span,
+    types::{Arg, ArgCond, CombinatorDecl, ResType, TLSchema, Term},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// A semantic problem found in a schema that parsed successfully.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// A conditional field like `x:flags.0?int` names a field that isn't
+    /// declared before it.
+    UnknownFlags {
+        decl: String,
+        field: String,
+        flags: String,
+        span: Span,
+    },
+    /// A conditional field refers to a field that isn't of type `#`.
+    FlagsNotNat {
+        decl: String,
+        field: String,
+        flags: String,
+        span: Span,
+    },
+    /// Flag bits only go from 0 to 31.
+    FlagBitOutOfRange {
+        decl: String,
+        field: String,
+        bit: u32,
+        span: Span,
+    },
+    /// A type that is neither declared by the schema nor a type variable.
+    UnknownType {
+        decl: String,
+        name: String,
+        span: Span,
+    },
+    /// A function result that is neither a declared type nor a constructor.
+    UnknownResultType {
+        decl: String,
+        name: String,
+        span: Span,
+    },
+    /// Two different declarations sharing a constructor ID.
+    DuplicateId {
+        id: u32,
+        first: String,
+        second: String,
+        span: Span,
+    },
+    /// Two different declarations sharing a name.
+    DuplicateName { name: String, span: Span },
+}
+
+impl ValidationError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UnknownFlags { span, .. }
+            | Self::FlagsNotNat { span, .. }
+            | Self::FlagBitOutOfRange { span, .. }
+            | Self::UnknownType { span, .. }
+            | Self::UnknownResultType { span, .. }
+            | Self::DuplicateId { span, .. }
+            | Self::DuplicateName { span, .. } => *span,
+        }
+    }
+
+    /// The error without its position.
+    pub fn message(&self) -> String {
+        match self {
+            Self::UnknownFlags {
+                decl, field, flags, ..
+            } => format!(
+                "{}: field `{}` depends on `{}`, which is not declared before it",
+                decl, field, flags
+            ),
+            Self::FlagsNotNat {
+                decl, field, flags, ..
+            } => format!(
+                "{}: field `{}` depends on `{}`, which is not of type `#`",
+                decl, field, flags
+            ),
+            Self::FlagBitOutOfRange {
+                decl, field, bit, ..
+            } => format!(
+                "{}: field `{}` uses flag bit {}, but only bits 0 to 31 exist",
+                decl, field, bit
+            ),
+            Self::UnknownType { decl, name, .. } => {
+                format!("{}: unknown type `{}`", decl, name)
+            }
+            Self::UnknownResultType { decl, name, .. } => {
+                format!("{}: unknown result type `{}`", decl, name)
+            }
+            Self::DuplicateId {
+                id, first, second, ..
+            } => format!(
+                "{}: constructor id #{:08x} is already used by {}",
+                second, id, first
+            ),
+            Self::DuplicateName { name, .. } => {
+                format!("{}: declared more than once", name)
+            }
+        }
+    }
+
+    /// The error as a diagnostic pointing into `source`, the text the
+    /// declaration was parsed from.
+    pub fn diagnostic(&self, source: &str, file: Option<&str>) -> Diagnostic {
+        Diagnostic::new(source, self.span().start, self.message(), file)
+    }
+}
+
+/// Prefixed with `line:column` when the declaration was parsed from text.
+/// A schema doesn't know which file each declaration came from; use
+/// [`ValidationError::diagnostic`] to name it.
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        if span.line > 0 {
+            write!(f, "{}:{}: ", span.line, span.column)?;
+        }
+        f.write_str(&self.message())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Names of the types a schema declares.
+struct Known {
+    /// Boxed types, like `Vector` or `help.ConfigSimple`.
+    boxed: HashSet<String>,
+    /// Bare types, i.e. constructor and builtin names.
+    bare: HashSet<String>,
+}
+
+impl Known {
+    /// `Type` is the type of type variables and `bytes` is `string` under a
+    /// different name; neither is declared by the schemas.
+    fn contains(&self, name: &str) -> bool {
+        matches!(name, "Type" | "bytes") || self.boxed.contains(name) || self.bare.contains(name)
+    }
+}
+
+impl<'a> TLSchema<'a> {
+    /// Checks the schema for mistakes the grammar can't catch: broken flag
+    /// fields, undeclared types and clashing declarations. Types are looked
+    /// up in this schema only, so schemas split over several files should be
+    /// merged first.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let known = Known {
+            boxed: self
+                .constrs
+                .iter()
+                .map(|d| res_name(&d.res))
+                .chain(self.builtin.iter().map(|d| d.res.to_string()))
+                .collect(),
+            bare: self
+                .constrs
+                .iter()
+                .map(|d| d.identns.to_string())
+                .chain(self.builtin.iter().map(|d| d.identns.to_string()))
+                .collect(),
+        };
+
+        let mut errors = vec![];
+
+        for decl in self.constrs.iter().chain(&self.funcs) {
+            validate_decl(decl, &known, &mut errors);
+        }
+
+        for decl in &self.funcs {
+            let name = res_name(&decl.res);
+            let vars = type_vars(decl);
+            if !known.contains(&name) && !vars.contains(name.as_str()) {
+                errors.push(ValidationError::UnknownResultType {
+                    decl: decl.identns.to_string(),
+                    name,
+                    span: decl.res.span(),
+                });
+            }
+            if let ResType::Ang(r) = &decl.res {
+                let decl = decl.identns.to_string();
+                std::iter::once(&r.term)
+                    .chain(&r.terms)
+                    .for_each(|t| validate_term(t, &decl, &known, &vars, &mut errors));
+            }
+        }
+
+        validate_duplicates(self, &mut errors);
+
+        errors
+    }
+}
+
+fn res_name(res: &ResType) -> String {
+    match res {
+        ResType::Normal(r) => r.identns.to_string(),
+        ResType::Ang(r) => r.identns.to_string(),
+    }
+}
+
+/// Type and nat variables a declaration binds: `{X:Type}`, `{n:#}` and
+/// fields of type `#`.
+fn type_vars<'d>(decl: &'d CombinatorDecl) -> HashSet<&'d str> {
+    let opt_args = decl
+        .opt_args
+        .iter()
+        .flat_map(|a| std::iter::once(&a.ident).chain(&a.idents))
+        .map(|i| i.as_ref());
+    let nat_args = decl.args.iter().filter_map(|a| match a {
+        Arg::Cond(ArgCond {
+            ident,
+            term: Term::Nat(_),
+            ..
+        }) => Some(ident.as_ref()),
+        _ => None,
+    });
+
+    opt_args.chain(nat_args).collect()
+}
+
+fn validate_decl(decl: &CombinatorDecl, known: &Known, errors: &mut Vec<ValidationError>) {
+    let name = decl.identns.to_string();
+    let vars = type_vars(decl);
+
+    for opt_arg in &decl.opt_args {
+        for term in &opt_arg.terms {
+            validate_term(term, &name, known, &vars, errors);
+        }
+    }
+
+    for (i, arg) in decl.args.iter().enumerate() {
+        if let Arg::Cond(ArgCond {
+            ident,
+            cond: Some(cond),
+            span,
+            ..
+        }) = arg
+        {
+            let flags = decl.args[..i].iter().find_map(|a| match a {
+                Arg::Cond(a) if a.ident == cond.ident => Some(&a.term),
+                _ => None,
+            });
+            let is_opt_arg = decl.opt_args.iter().any(|a| {
+                std::iter::once(&a.ident)
+                    .chain(&a.idents)
+                    .any(|i| *i == cond.ident)
+                    && matches!(a.terms.as_slice(), [Term::Nat(_)])
+            });
+
+            match flags {
+                Some(Term::Nat(_)) => (),
+                None if is_opt_arg => (),
+                Some(_) => errors.push(ValidationError::FlagsNotNat {
+                    decl: name.clone(),
+                    field: ident.to_string(),
+                    flags: cond.ident.to_string(),
+                    span: *span,
+                }),
+                None => errors.push(ValidationError::UnknownFlags {
+                    decl: name.clone(),
+                    field: ident.to_string(),
+                    flags: cond.ident.to_string(),
+                    span: *span,
+                }),
+            }
+
+            if let Some(bit @ 32..) = cond.index {
+                errors.push(ValidationError::FlagBitOutOfRange {
+                    decl: name.clone(),
+                    field: ident.to_string(),
+                    bit,
+                    span: *span,
+                });
+            }
+        }
+
+        validate_arg(arg, &name, known, &vars, errors);
+    }
+}
+
+fn validate_arg(
+    arg: &Arg,
+    decl: &str,
+    known: &Known,
+    vars: &HashSet<&str>,
+    errors: &mut Vec<ValidationError>,
+) {
+    match arg {
+        Arg::Par(a) => validate_term(&a.term, decl, known, vars, errors),
+        Arg::Cond(a) => validate_term(&a.term, decl, known, vars, errors),
+        Arg::Single(a) => validate_term(&a.term, decl, known, vars, errors),
+        Arg::Brack(a) => a
+            .args
+            .iter()
+            .for_each(|a| validate_arg(a, decl, known, vars, errors)),
+    }
+}
+
+fn validate_term(
+    term: &Term,
+    decl: &str,
+    known: &Known,
+    vars: &HashSet<&str>,
+    errors: &mut Vec<ValidationError>,
+) {
+    match term {
+        Term::IdentNs(i) => {
+            let name = i.to_string();
+            if !known.contains(&name) && !vars.contains(name.as_str()) {
+                errors.push(ValidationError::UnknownType {
+                    decl: decl.to_string(),
+                    name,
+                    span: i.span,
+                });
+            }
+        }
+        Term::Ang(a) => {
+            validate_term(&Term::IdentNs(a.identns.clone()), decl, known, vars, errors);
+            std::iter::once(a.term.as_ref())
+                .chain(&a.terms)
+                .for_each(|t| validate_term(t, decl, known, vars, errors));
+        }
+        Term::Par(terms, _) => terms
+            .iter()
+            .for_each(|t| validate_term(t, decl, known, vars, errors)),
+        Term::Percent(t) | Term::Plus(t, ..) => validate_term(t, decl, known, vars, errors),
+        Term::Nat(_) | Term::NatConst(..) => (),
+    }
+}
+
+/// Reports declarations that reuse the ID or name of a different one. The
+/// same declaration repeated, like `vector` in both api.tl and mtproto.tl,
+/// is fine.
+fn validate_duplicates(schema: &TLSchema, errors: &mut Vec<ValidationError>) {
+    let mut ids: HashMap<u32, (String, String)> = HashMap::new();
+    let mut constr_names: HashMap<String, String> = HashMap::new();
+    let mut func_names: HashMap<String, String> = HashMap::new();
+
+    for (decl, is_func) in schema
+        .constrs
+        .iter()
+        .map(|d| (d, false))
+        .chain(schema.funcs.iter().map(|d| (d, true)))
+    {
+        let name = decl.identns.to_string();
+        let repr = decl.canonical_repr();
+        let id = decl.get_id();
+
+        match ids.get(&id) {
+            Some((first, first_repr)) if *first_repr != repr => {
+                errors.push(ValidationError::DuplicateId {
+                    id,
+                    first: first.clone(),
+                    second: name.clone(),
+                    span: decl.span,
+                })
+            }
+            Some(_) => (),
+            None => {
+                ids.insert(id, (name.clone(), repr.clone()));
+            }
+        }
+
+        let names = match is_func {
+            false => &mut constr_names,
+            true => &mut func_names,
+        };
+        match names.get(&name) {
+            Some(first_repr) if *first_repr != repr => {
+                errors.push(ValidationError::DuplicateName {
+                    name,
+                    span: decl.span,
+                })
+            }
+            Some(_) => (),
+            None => {
+                names.insert(name, repr);
+            }
+        }
+    }
+
+    let mut builtins: HashMap<String, String> = HashMap::new();
+    for decl in &schema.builtin {
+        let name = decl.identns.to_string();
+        let repr = decl.res.to_string();
+        match builtins.get(&name) {
+            Some(first_res) if *first_res != repr => errors.push(ValidationError::DuplicateName {
+                name,
+                span: decl.span,
+            }),
+            Some(_) => (),
+            None => {
+                builtins.insert(name, repr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ValidationError;
+    use crate::combinators::schema;
+
+    fn validate(text: &str) -> Vec<ValidationError> {
+        let base = "
+            int ? = Int;
+            true#3fedd339 = True;
+            vector#1cb5c415 {t:Type} # [ t ] = Vector t;
+        ";
+        // appended rather than merged, to keep the duplicates being tested
+        let mut schema = schema(base).unwrap();
+        let other = crate::combinators::schema(text).unwrap();
+        schema.builtin.extend(other.builtin);
+        schema.constrs.extend(other.constrs);
+        schema.funcs.extend(other.funcs);
+        schema.validate()
+    }
+
+    #[test]
+    fn test_validate_valid() {
+        let errors = validate(
+            "
+            user#1 flags:# self:flags.0?true id:int friends:flags.31?Vector<User> = User;
+            ---functions---
+            invokeWithLayer#da9b0d0d {X:Type} layer:int query:!X = X;
+            getUsers#2 ids:Vector<int> = Vector<User>;
+        ",
+        );
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn test_validate_flags() {
+        let errors = validate(
+            "
+            a#1 x:flags.0?int flags:# = A;
+            b#2 flags:int x:flags.0?int = B;
+            c#3 flags:# x:flags.32?int = C;
+        ",
+        );
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                ValidationError::UnknownFlags { decl: a, .. },
+                ValidationError::FlagsNotNat { decl: b, .. },
+                ValidationError::FlagBitOutOfRange { decl: c, bit: 32, .. },
+            ] if a == "a" && b == "b" && c == "c"
+        ));
+        assert_eq!(
+            errors[0].to_string(),
+            "2:17: a: field `x` depends on `flags`, which is not declared before it"
+        );
+    }
+
+    #[test]
+    fn test_validate_types() {
+        let errors = validate(
+            "
+            a#1 x:Foo y:Vector<bar> = A;
+            ---functions---
+            getA#2 = A;
+            getB#3 = B;
+            getC#4 = Vector<C>;
+        ",
+        );
+        let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "2:19: a: unknown type `Foo`",
+                "2:32: a: unknown type `bar`",
+                "5:22: getB: unknown result type `B`",
+                "6:29: getC: unknown type `C`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_duplicates() {
+        let errors = validate(
+            "
+            vector#1cb5c415 {t:Type} # [ t ] = Vector t;
+            a#1 = A;
+            a#2 x:int = A;
+            b#1 = B;
+        ",
+        );
+        let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "4:13: a: declared more than once",
+                "5:13: b: constructor id #00000001 is already used by a",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_diagnostic() {
+        let text = "---types---\nfoo#1 x:Bar = Foo;\n";
+        let errors = schema(text).unwrap().validate();
+
+        let diagnostic = errors[0].diagnostic(text, Some("foo.tl"));
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 9));
+        assert_eq!(diagnostic.message, "foo: unknown type `Bar`");
+        assert!(diagnostic.to_string().contains("--> foo.tl:2:9"));
+    }
+}
@@ -1,30 +1,115 @@
-use gen::generate::generate_code;
-use parser::{combinators::schema, types::TLSchema};
-use std::{env, fs::File, io::Read, path::Path};
+use gen::{config::GenConfig, generate::generate_files};
+use parser::{
+    combinators::{schema_with_options, ParseOptions},
+    errors::ParsingError,
+    merge::MergePolicy,
+    span::Span,
+    types::TLSchema,
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
 
-fn main() {
-    let mut api_fs = File::open("../data/schema/api.tl").unwrap();
-    let mut mtproto_fs = File::open("../data/schema/mtproto.tl").unwrap();
+/// Schema files the bindings are generated from, merged in order.
+///
+/// More files, like extension schemas for secret chats or test
+/// constructors, can be listed in `TL_SCHEMAS`, separated like `PATH`.
+/// Cargo runs this script from the `raw` directory, so relative paths
+/// there are relative to `raw/`, not to where `cargo` was run.
+/// `TL_MERGE_POLICY` (`error`, `override` or `keep-first`) decides what
+/// happens when they redeclare a name; by default it's an error.
+const SCHEMAS: &[&str] = &["../data/schema/api.tl", "../data/schema/mtproto.tl"];
+
+/// A schema file, kept around to point validation errors of the merged
+/// schema at the file they're in.
+struct SchemaFile {
+    path: String,
+    text: String,
+    schema: TLSchema<'static>,
+}
+
+impl SchemaFile {
+    fn read(path: &Path) -> Result<Self, ParsingError> {
+        let text = fs::read_to_string(path)?;
+        let path = path.display().to_string();
+        let options = ParseOptions {
+            file_name: Some(&path),
+            recover: true,
+        };
+        let mut schema = schema_with_options(&text, options)?.into_owned();
+        schema.calculate_ids();
+
+        Ok(Self { path, text, schema })
+    }
 
-    let mut api_text = String::new();
-    let mut mtproto_text = String::new();
+    /// Whether `span` points into a declaration of this file that made it
+    /// into `merged`. Spans are offsets into the file they were parsed
+    /// from, so the declaration itself has to match too.
+    fn contains(&self, merged: &TLSchema, span: Span) -> bool {
+        let within = |decl: Span| decl.start <= span.start && span.end <= decl.end;
+        let combinators = self
+            .schema
+            .constrs
+            .iter()
+            .chain(&self.schema.funcs)
+            .filter(|d| within(d.span))
+            .any(|d| merged.constrs.contains(d) || merged.funcs.contains(d));
+        let builtins = self
+            .schema
+            .builtin
+            .iter()
+            .filter(|d| within(d.span))
+            .any(|d| merged.builtin.contains(d));
 
-    mtproto_fs.read_to_string(&mut mtproto_text).unwrap();
-    api_fs.read_to_string(&mut api_text).unwrap();
+        combinators || builtins
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=TL_SCHEMAS");
+    println!("cargo:rerun-if-env-changed=TL_MERGE_POLICY");
 
-    let api_schema = schema(&api_text).unwrap().1;
-    let mtproto_schema = schema(&mtproto_text).unwrap().1;
+    let mut paths = SCHEMAS.iter().map(PathBuf::from).collect::<Vec<_>>();
+    if let Some(extra) = env::var_os("TL_SCHEMAS") {
+        paths.extend(env::split_paths(&extra));
+    }
+    for path in &paths {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+    let policy = match env::var("TL_MERGE_POLICY") {
+        Ok(policy) => policy.parse().unwrap_or_else(|e| panic!("{}", e)),
+        Err(_) => MergePolicy::Error,
+    };
 
+    let mut files = vec![];
     let mut schema = TLSchema::default();
-    schema.extend(api_schema);
-    schema.extend(mtproto_schema);
-    schema.calculate_ids();
-
-    let dest_path = Path::new(&env::var_os("OUT_DIR").unwrap()).join("generated.rs");
-    let mut fs = File::options()
-        .create(true)
-        .write(true)
-        .open(dest_path)
-        .unwrap();
-    generate_code(&mut fs, schema, 142).unwrap();
+    for path in &paths {
+        let file = SchemaFile::read(path).unwrap_or_else(|e| panic!("\n{}", e));
+        let collisions = schema
+            .merge(file.schema.clone(), policy)
+            .unwrap_or_else(|e| panic!("\n{}", e));
+        for collision in collisions {
+            println!("cargo:warning={}", collision);
+        }
+        files.push(file);
+    }
+
+    let errors = schema.validate();
+    if !errors.is_empty() {
+        let messages = errors
+            .iter()
+            .map(
+                |e| match files.iter().find(|f| f.contains(&schema, e.span())) {
+                    Some(file) => e.diagnostic(&file.text, Some(&file.path)).to_string(),
+                    None => e.to_string(),
+                },
+            )
+            .collect::<Vec<_>>();
+        panic!("invalid schema:\n{}", messages.join("\n"));
+    }
+
+    let dest_path = Path::new(&env::var_os("OUT_DIR").unwrap()).join("generated");
+    let config = GenConfig::new().feature_gates(true).require_layer(true);
+    generate_files(&dest_path, schema, &config).unwrap_or_else(|e| panic!("{}", e));
 }
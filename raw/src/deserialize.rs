@@ -5,15 +5,28 @@ pub type Result<T> = std::result::Result<T, crate::Err>;
 pub struct Cursor<'a>(std::io::Cursor<&'a [u8]>);
 
 impl<'a> Cursor<'a> {
+    pub fn from_slice(buf: &'a [u8]) -> Self {
+        Self(std::io::Cursor::new(buf))
+    }
+
+    /// How many bytes have been read so far.
+    pub fn pos(&self) -> usize {
+        self.0.position() as usize
+    }
+
     fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
         self.0.read_exact(buf).map_err(crate::Err::from)
     }
 }
 
-pub type Buf<'a> = &'a mut Cursor<'a>;
+pub type Buf<'a, 'b> = &'a mut Cursor<'b>;
 
 pub trait Deserializable: Sized {
     fn deserialize(buf: Buf) -> Result<Self>;
+
+    fn from_bytes(buf: &[u8]) -> Result<Self> {
+        Self::deserialize(&mut Cursor::from_slice(buf))
+    }
 }
 
 impl Deserializable for i32 {
@@ -48,6 +61,48 @@ impl Deserializable for f64 {
     }
 }
 
+impl Deserializable for [u8; 16] {
+    fn deserialize(buf: Buf) -> Result<Self> {
+        let mut buffer = [0u8; 16];
+        buf.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+impl Deserializable for [u8; 32] {
+    fn deserialize(buf: Buf) -> Result<Self> {
+        let mut buffer = [0u8; 32];
+        buf.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+impl<T: Deserializable> Deserializable for Vec<T> {
+    fn deserialize(buf: Buf) -> Result<Self> {
+        let id = u32::deserialize(buf)?;
+        if id != 0x1cb5c415 {
+            return Err(crate::Err::UnexpectedConstructor { id, ty: "Vector" });
+        }
+        Ok(crate::RawVec::<T>::deserialize(buf)?.0)
+    }
+}
+
+impl<T: Deserializable> Deserializable for crate::RawVec<T> {
+    fn deserialize(buf: Buf) -> Result<Self> {
+        let len = u32::deserialize(buf)?;
+        (0..len)
+            .map(|_| T::deserialize(buf))
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+impl<T: Deserializable> Deserializable for Box<T> {
+    fn deserialize(buf: Buf) -> Result<Self> {
+        T::deserialize(buf).map(Box::new)
+    }
+}
+
 impl Deserializable for Vec<u8> {
     fn deserialize(buf: Buf) -> Result<Self> {
         let mut buffer = [0u8; 1];
@@ -6,11 +6,31 @@ pub use deserialize::{Deserializable, Result};
 pub use generated::{enums, functions, types};
 pub use serialize::Serializable;
 
+#[derive(Debug)]
 pub enum Err {
     IO(std::io::Error),
     InvalidUtf8,
+    /// A boxed value started with an ID that none of its constructors have.
+    UnexpectedConstructor {
+        id: u32,
+        ty: &'static str,
+    },
 }
 
+impl std::fmt::Display for Err {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "{}", e),
+            Self::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            Self::UnexpectedConstructor { id, ty } => {
+                write!(f, "unexpected constructor {:#010x} for type {}", id, ty)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Err {}
+
 impl From<std::io::Error> for Err {
     fn from(e: std::io::Error) -> Self {
         Self::IO(e)
@@ -41,3 +61,278 @@ pub trait Request: Serializable {
 pub struct RawVec<T>(Vec<T>);
 pub type Int128 = [u8; 16];
 pub type Int256 = [u8; 32];
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        enums, functions, types, Deserializable, Err, Identificable, Request, Serializable,
+    };
+
+    fn roundtrip<T: Serializable + Deserializable>(value: &T) -> T {
+        let mut buf = vec![];
+        value.serialize(&mut buf);
+        T::from_bytes(&buf).unwrap()
+    }
+
+    #[test]
+    fn test_deserialize_struct() {
+        let res_pq = types::ResPq {
+            nonce: [1; 16],
+            server_nonce: [2; 16],
+            pq: "17ED48941A08F981".into(),
+            server_public_key_fingerprints: vec![-0x3e9b_1d1c_ad6c_af4a],
+        };
+        assert_eq!(roundtrip(&res_pq), res_pq);
+
+        let settings = types::InputPeerNotifySettings {
+            show_previews: Some(enums::Bool::BoolTrue(types::BoolTrue {})),
+            silent: None,
+            mute_until: Some(3600),
+            sound: None,
+        };
+        assert_eq!(roundtrip(&settings), settings);
+        assert_eq!(
+            roundtrip(&enums::InputPeerNotifySettings::InputPeerNotifySettings(
+                settings.clone()
+            )),
+            enums::InputPeerNotifySettings::InputPeerNotifySettings(settings)
+        );
+    }
+
+    #[test]
+    fn test_flags_message() {
+        let message = types::Message {
+            out: true,
+            mentioned: false,
+            media_unread: false,
+            silent: false,
+            post: false,
+            from_scheduled: false,
+            legacy: false,
+            edit_hide: false,
+            pinned: true,
+            noforwards: false,
+            id: 7,
+            from_id: Some(enums::Peer::PeerUser(types::PeerUser { user_id: 1 })),
+            peer_id: enums::Peer::PeerUser(types::PeerUser { user_id: 2 }),
+            fwd_from: None,
+            via_bot_id: None,
+            reply_to: None,
+            date: 1_650_000_000,
+            message: "hello".into(),
+            media: None,
+            reply_markup: None,
+            entities: Some(vec![enums::MessageEntity::MessageEntityBold(
+                types::MessageEntityBold {
+                    offset: 0,
+                    length: 5,
+                },
+            )]),
+            views: Some(3),
+            forwards: Some(1),
+            replies: None,
+            edit_date: None,
+            post_author: None,
+            grouped_id: None,
+            reactions: None,
+            restriction_reason: None,
+            ttl_period: None,
+        };
+
+        let mut buf = vec![];
+        message.serialize(&mut buf);
+        let flags = 1 << 1 | 1 << 24 | 1 << 8 | 1 << 7 | 1 << 10;
+        assert_eq!(buf[..4], u32::to_le_bytes(flags));
+        assert_eq!(buf[4..8], 7i32.to_le_bytes());
+        assert_eq!(roundtrip(&message), message);
+    }
+
+    #[test]
+    fn test_flags_channel_full() {
+        let notify_settings = types::PeerNotifySettings {
+            show_previews: None,
+            silent: None,
+            mute_until: None,
+            ios_sound: None,
+            android_sound: None,
+            other_sound: None,
+        };
+        let channel = types::ChannelFull {
+            can_view_participants: false,
+            can_set_username: true,
+            can_set_stickers: false,
+            hidden_prehistory: false,
+            can_set_location: false,
+            has_scheduled: false,
+            can_view_stats: false,
+            blocked: false,
+            can_delete_channel: true,
+            id: 10,
+            about: "about".into(),
+            participants_count: Some(100),
+            admins_count: None,
+            kicked_count: None,
+            banned_count: None,
+            online_count: None,
+            read_inbox_max_id: 1,
+            read_outbox_max_id: 2,
+            unread_count: 0,
+            chat_photo: enums::Photo::PhotoEmpty(types::PhotoEmpty { id: 0 }),
+            notify_settings: enums::PeerNotifySettings::PeerNotifySettings(notify_settings),
+            exported_invite: None,
+            bot_info: vec![],
+            migrated_from_chat_id: None,
+            migrated_from_max_id: None,
+            pinned_msg_id: None,
+            stickerset: None,
+            available_min_id: None,
+            folder_id: None,
+            linked_chat_id: None,
+            location: None,
+            slowmode_seconds: None,
+            slowmode_next_send_date: None,
+            stats_dc: None,
+            pts: 5,
+            call: None,
+            ttl_period: None,
+            pending_suggestions: None,
+            groupcall_default_join_as: None,
+            theme_emoticon: None,
+            requests_pending: None,
+            recent_requesters: None,
+            default_send_as: None,
+            available_reactions: Some(vec!["👍".into()]),
+        };
+
+        let mut buf = vec![];
+        channel.serialize(&mut buf);
+        let flags = 1 << 6 | 1 << 0 | 1 << 30;
+        assert_eq!(buf[..4], u32::to_le_bytes(flags));
+        assert_eq!(buf[4..8], 1u32.to_le_bytes());
+        assert_eq!(roundtrip(&channel), channel);
+
+        // a set bit for a `true` flag is all there is to it
+        let mut unset = buf.clone();
+        unset[4..8].copy_from_slice(&0u32.to_le_bytes());
+        let channel = types::ChannelFull {
+            can_delete_channel: false,
+            ..channel
+        };
+        assert_eq!(types::ChannelFull::from_bytes(&unset).unwrap(), channel);
+    }
+
+    #[test]
+    fn test_deserialize_enum() {
+        let text = enums::RichText::TextBold(Box::new(types::TextBold {
+            text: enums::RichText::TextConcat(types::TextConcat {
+                texts: vec![
+                    enums::RichText::TextPlain(types::TextPlain {
+                        text: "bold".into(),
+                    }),
+                    enums::RichText::TextEmpty(types::TextEmpty {}),
+                ],
+            }),
+        }));
+        assert_eq!(roundtrip(&text), text);
+
+        let mut buf = vec![];
+        text.serialize(&mut buf);
+        buf[..4].copy_from_slice(&0xdead_beefu32.to_le_bytes());
+        let err = enums::RichText::from_bytes(&buf).unwrap_err();
+        assert!(matches!(
+            err,
+            Err::UnexpectedConstructor {
+                id: 0xdead_beef,
+                ty: "RichText"
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "unexpected constructor 0xdeadbeef for type RichText"
+        );
+    }
+
+    #[test]
+    fn test_function_request() {
+        fn serialize<R: Request>(request: &R) -> Vec<u8> {
+            let mut buf = vec![];
+            request.serialize(&mut buf);
+            buf
+        }
+        fn response<R: Request>(_: &R, bytes: &[u8]) -> R::Response {
+            R::Response::from_bytes(bytes).unwrap()
+        }
+
+        let ping = functions::Ping { ping_id: 42 };
+        let mut expected = 0x7abe77ecu32.to_le_bytes().to_vec();
+        expected.extend(42i64.to_le_bytes());
+        assert_eq!(functions::Ping::ID, 0x7abe77ec);
+        assert_eq!(serialize(&ping), expected);
+
+        let pong = types::Pong {
+            msg_id: 1,
+            ping_id: 42,
+        };
+        let mut bytes = vec![];
+        enums::Pong::Pong(pong.clone()).serialize(&mut bytes);
+        let enums::Pong::Pong(received) = response(&ping, &bytes);
+        assert_eq!(received, pong);
+
+        let get_messages = functions::messages::GetMessages { id: vec![] };
+        let bytes = serialize(&get_messages);
+        assert_eq!(bytes[..4], 0x63c66506u32.to_le_bytes());
+        assert_eq!(bytes[4..8], 0x1cb5c415u32.to_le_bytes());
+        fn returns<T, R: Request<Response = T>>(_: &R) {}
+        returns::<enums::messages::Messages, _>(&get_messages);
+        returns::<Vec<enums::User>, _>(&functions::users::GetUsers { id: vec![] });
+    }
+
+    #[test]
+    fn test_generic_request() {
+        fn returns<T, R: Request<Response = T>>(_: &R) {}
+
+        let request = functions::InvokeWithLayer {
+            layer: crate::generated::LAYER as i32,
+            query: functions::InitConnection {
+                api_id: 1,
+                device_model: "device".into(),
+                system_version: "1.0".into(),
+                app_version: "1.0".into(),
+                system_lang_code: "en".into(),
+                lang_pack: "".into(),
+                lang_code: "en".into(),
+                proxy: None,
+                params: None,
+                query: functions::help::GetConfig {},
+            },
+        };
+        returns::<enums::Config, _>(&request);
+
+        let mut buf = vec![];
+        request.serialize(&mut buf);
+        let mut expected = vec![];
+        functions::InvokeWithLayer::<functions::help::GetConfig>::ID.serialize(&mut expected);
+        request.layer.serialize(&mut expected);
+        request.query.serialize(&mut expected);
+        assert_eq!(buf, expected);
+        assert_eq!(
+            buf[buf.len() - 4..],
+            functions::help::GetConfig::ID.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        let mut buf = vec![];
+        0x1234_5678u32.serialize(&mut buf);
+        0i32.serialize(&mut buf);
+        assert!(matches!(
+            Vec::<i64>::from_bytes(&buf),
+            Err(Err::UnexpectedConstructor {
+                id: 0x1234_5678,
+                ty: "Vector"
+            })
+        ));
+        assert!(matches!(i64::from_bytes(&[0; 7]), Err(Err::IO(_))));
+    }
+}